@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use student::parse::{parse_expression, tokenize};
+
+fuzz_target!(|input: &str| {
+    if let Ok(tokens) = tokenize(input) {
+        if let Ok(ast) = parse_expression(&tokens) {
+            let _ = ast.to_string();
+        }
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Feed the input to the REPL exactly as a user would, one line at a time.
+fuzz_target!(|input: &str| {
+    let mut out = Vec::new();
+    student::repl::run(input.as_bytes(), &mut out).unwrap();
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use student::parse::tokenize;
+
+fuzz_target!(|input: &str| {
+    if let Ok(tokens) = tokenize(input) {
+        for t in &tokens {
+            let _ = t.to_string();
+        }
+    }
+});
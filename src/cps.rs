@@ -0,0 +1,553 @@
+use super::*;
+use interp::{cast, concat, less_than, plus, print, raise};
+
+// An interpreter in continuation-passing style, with the continuation
+// kept as data: a stack of frames, each saying what to do with the value
+// of the expression being evaluated. Since nothing is left on the host
+// stack, letcc can capture the rest of the computation by copying the
+// frames, and throwing to a continuation replaces them. It evaluates
+// call-by-value, in the same order as `interp`, and gives the same
+// results and errors on programs without letcc. An error unwinds the
+// frames to the innermost try's, so that runtime errors can be caught as
+// well as raised exceptions. A perform looks down the frames for the
+// innermost handler of its operation, and the frames above that, the
+// handler's own included, become the continuation its clause can resume.
+
+// Whether `e` uses a form that only this interpreter runs, so that the
+// REPL evaluates it here whatever it was asked for.
+pub fn required(e: &Exp) -> bool {
+    match e {
+        Exp::LetCC { .. } | Exp::Perform { .. } => true,
+        Exp::Int(_)
+        | Exp::Float(_)
+        | Exp::Bool(_)
+        | Exp::Str(_)
+        | Exp::Unit
+        | Exp::Var(_) => false,
+        Exp::Plus { left, right }
+        | Exp::Concat { left, right }
+        | Exp::LessThan { left, right } => required(left) || required(right),
+        Exp::Cnd { tst, thn, els } => {
+            required(tst) || required(thn) || required(els)
+        }
+        Exp::Let1 { value, body, .. } => required(value) || required(body),
+        Exp::Lam { body, .. } => required(body),
+        Exp::App { fun, arg } => required(fun) || required(arg),
+        Exp::Seq { first, second } => required(first) || required(second),
+        Exp::Print { arg } | Exp::Raise { arg } => required(arg),
+        Exp::Ascribe { exp, .. }
+        | Exp::Cast { exp, .. }
+        | Exp::At { exp, .. } => required(exp),
+        Exp::Try { body, handler, .. } => required(body) || required(handler),
+        Exp::Handle { body, clauses, ret } => {
+            required(body)
+                || clauses.iter().any(|c| required(&c.body))
+                || ret.as_ref().is_some_and(|r| required(&r.body))
+        }
+    }
+}
+
+// What to do with a value, the innermost frame last.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Frame {
+    // the left operand of `op` is done; evaluate the right
+    Right { op: Op, right: Rc<Exp>, nv: Env },
+    // both operands are done; apply `op`
+    Operate { op: Op, left: Value },
+    // the condition is done; evaluate a branch
+    Branch { thn: Rc<Exp>, els: Rc<Exp>, nv: Env },
+    // the let value is done; bind it and evaluate the body
+    Bind { var: String, body: Rc<Exp>, nv: Env },
+    // the function is done; evaluate the argument
+    Arg { arg: Rc<Exp>, nv: Env },
+    // the argument is done; call the function
+    Call { fun: Value },
+    // the first part of a sequence is done; evaluate the second
+    Then { second: Rc<Exp>, nv: Env },
+    Print,
+    // the exception is done; raise it
+    Raise,
+    // the body of a try is done; a value passes through, an exception is
+    // caught here
+    Catch { var: String, handler: Rc<Exp>, nv: Env },
+    // the argument is done; hand the operation to its handler
+    Perform { op: String },
+    // the handled expression is done; apply the return clause. Until
+    // then, performs of the clauses' operations stop here
+    Handle { clauses: Vec<Clause>, ret: Option<Return>, nv: Env },
+    // the value is done; check it is a `to`
+    Cast { from: Box<Type>, to: Box<Type>, label: String },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    Plus,
+    Concat,
+    LessThan,
+}
+
+// Where the machine is: about to evaluate an expression, or returning a
+// value to the top frame. Code is shared with the tree it came from, so
+// frames and calls never copy it.
+enum State {
+    Eval(Rc<Exp>, Env),
+    Return(Value),
+}
+
+pub fn eval(e: &Exp, nv: &Env) -> Result<Value, String> {
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut state = State::Eval(Rc::new(e.clone()), nv.clone());
+    loop {
+        let next = match state {
+            State::Eval(e, nv) => eval_step(&e, nv, &mut frames),
+            State::Return(v) => match frames.pop() {
+                Some(frame) => return_step(frame, v, &mut frames),
+                None => return Ok(v),
+            },
+        };
+        state = match next {
+            Ok(state) => state,
+            Err(msg) => catch(msg, &mut frames)?,
+        };
+    }
+}
+
+// Unwind to the innermost try, and evaluate its handler with `msg`; with
+// no try left the exception escapes.
+fn catch(msg: String, frames: &mut Vec<Frame>) -> Result<State, String> {
+    while let Some(frame) = frames.pop() {
+        if let Frame::Catch { var, handler, mut nv } = frame {
+            nv.insert(var, Value::Str(msg));
+            return Ok(State::Eval(handler, nv));
+        }
+    }
+    Err(msg)
+}
+
+// Start evaluating `e`, pushing a frame for whatever comes after its
+// first part.
+fn eval_step(
+    e: &Exp,
+    mut nv: Env,
+    frames: &mut Vec<Frame>,
+) -> Result<State, String> {
+    Ok(match e {
+        Exp::Int(n) => State::Return(Value::Int(*n)),
+        Exp::Float(x) => State::Return(Value::Float(*x)),
+        Exp::Bool(b) => State::Return(Value::Bool(*b)),
+        Exp::Str(s) => State::Return(Value::Str(s.clone())),
+        Exp::Unit => State::Return(Value::Unit),
+
+        Exp::Var(var) => match nv.remove(var) {
+            Some(v) => State::Return(v),
+            None if prims::prim_type(var).is_some() => {
+                State::Return(Value::Prim { name: var.clone(), args: vec![] })
+            }
+            None => return Err(format!("{var} not bound")),
+        },
+
+        Exp::Plus { left, right } => {
+            let right = Rc::clone(right);
+            frames.push(Frame::Right { op: Op::Plus, right, nv: nv.clone() });
+            State::Eval(Rc::clone(left), nv)
+        }
+
+        Exp::Concat { left, right } => {
+            let right = Rc::clone(right);
+            frames.push(Frame::Right { op: Op::Concat, right, nv: nv.clone() });
+            State::Eval(Rc::clone(left), nv)
+        }
+
+        Exp::LessThan { left, right } => {
+            let right = Rc::clone(right);
+            frames.push(Frame::Right {
+                op: Op::LessThan,
+                right,
+                nv: nv.clone(),
+            });
+            State::Eval(Rc::clone(left), nv)
+        }
+
+        Exp::Cnd { tst, thn, els } => {
+            let (thn, els) = (Rc::clone(thn), Rc::clone(els));
+            frames.push(Frame::Branch { thn, els, nv: nv.clone() });
+            State::Eval(Rc::clone(tst), nv)
+        }
+
+        Exp::Let1 { var, value, body } => {
+            let (var, body) = (var.clone(), Rc::clone(body));
+            frames.push(Frame::Bind { var, body, nv: nv.clone() });
+            State::Eval(Rc::clone(value), nv)
+        }
+
+        Exp::Lam { var, var_type, body } => State::Return(Value::Fun {
+            var: var.clone(),
+            var_type: var_type.clone().unwrap_or(Type::Dyn),
+            body: Rc::clone(body),
+            nv,
+        }),
+
+        Exp::App { fun, arg } => {
+            frames.push(Frame::Arg { arg: Rc::clone(arg), nv: nv.clone() });
+            State::Eval(Rc::clone(fun), nv)
+        }
+
+        Exp::Seq { first, second } => {
+            let second = Rc::clone(second);
+            frames.push(Frame::Then { second, nv: nv.clone() });
+            State::Eval(Rc::clone(first), nv)
+        }
+
+        Exp::Print { arg } => {
+            frames.push(Frame::Print);
+            State::Eval(Rc::clone(arg), nv)
+        }
+
+        // types are only checked statically
+        Exp::Ascribe { exp, .. } | Exp::At { exp, .. } => {
+            State::Eval(Rc::clone(exp), nv)
+        }
+
+        Exp::LetCC { var, var_type, body } => {
+            let typ = var_type.clone();
+            let k = Value::Cont { typ, frames: frames.clone() };
+            nv.insert(var.clone(), k);
+            State::Eval(Rc::clone(body), nv)
+        }
+
+        Exp::Raise { arg } => {
+            frames.push(Frame::Raise);
+            State::Eval(Rc::clone(arg), nv)
+        }
+
+        Exp::Cast { exp, from, to, label } => {
+            let (from, to) = (from.clone(), to.clone());
+            frames.push(Frame::Cast { from, to, label: label.clone() });
+            State::Eval(Rc::clone(exp), nv)
+        }
+
+        Exp::Try { body, var, handler } => {
+            let (var, handler) = (var.clone(), Rc::clone(handler));
+            frames.push(Frame::Catch { var, handler, nv: nv.clone() });
+            State::Eval(Rc::clone(body), nv)
+        }
+
+        Exp::Perform { op, arg, .. } => {
+            frames.push(Frame::Perform { op: op.clone() });
+            State::Eval(Rc::clone(arg), nv)
+        }
+
+        Exp::Handle { body, clauses, ret } => {
+            let (clauses, ret) = (clauses.clone(), ret.clone());
+            frames.push(Frame::Handle { clauses, ret, nv: nv.clone() });
+            State::Eval(Rc::clone(body), nv)
+        }
+    })
+}
+
+// Give `v` to the frame just popped.
+fn return_step(
+    frame: Frame,
+    v: Value,
+    frames: &mut Vec<Frame>,
+) -> Result<State, String> {
+    Ok(match frame {
+        Frame::Right { op, right, nv } => {
+            frames.push(Frame::Operate { op, left: v });
+            State::Eval(right, nv)
+        }
+
+        Frame::Operate { op, left } => State::Return(match op {
+            Op::Plus => plus(left, v)?,
+            Op::Concat => concat(left, v)?,
+            Op::LessThan => less_than(left, v)?,
+        }),
+
+        Frame::Branch { thn, els, nv } => match v {
+            Value::Bool(true) => State::Eval(thn, nv),
+            Value::Bool(false) => State::Eval(els, nv),
+            v => return Err(format!("boolean expected, found {:?}", v)),
+        },
+
+        Frame::Bind { var, body, mut nv } => {
+            nv.insert(var, v);
+            State::Eval(body, nv)
+        }
+
+        Frame::Arg { arg, nv } => {
+            frames.push(Frame::Call { fun: v });
+            State::Eval(arg, nv)
+        }
+
+        // a call pushes no frame of its own, so tail calls take no space
+        Frame::Call { fun } => match fun {
+            Value::Fun { var, body, mut nv, .. } => {
+                nv.insert(var, v);
+                State::Eval(body, nv)
+            }
+            Value::Prim { name, args } => {
+                State::Return(prims::apply(&name, &args, v)?)
+            }
+            // throw away the current continuation for the captured one
+            Value::Cont { frames: captured, .. } => {
+                *frames = captured;
+                State::Return(v)
+            }
+            // resuming runs the captured frames on top of the current ones
+            Value::Resume { frames: captured } => {
+                frames.extend(captured);
+                State::Return(v)
+            }
+            fun => return Err(format!("function expected, found {:?}", fun)),
+        },
+
+        Frame::Then { second, nv } => State::Eval(second, nv),
+
+        Frame::Print => State::Return(print(v)?),
+
+        Frame::Raise => return Err(raise(v)),
+
+        Frame::Cast { from, to, label } => {
+            State::Return(cast(v, &from, &to, &label)?)
+        }
+
+        Frame::Catch { .. } => State::Return(v),
+
+        Frame::Perform { op } => perform(op, v, frames)?,
+
+        Frame::Handle { ret, nv, .. } => match ret {
+            Some(Return { var, body }) => {
+                let mut nv = nv;
+                nv.insert(var, v);
+                State::Eval(body, nv)
+            }
+            None => State::Return(v),
+        },
+    })
+}
+
+// Run the clause for `op` of the innermost handler that has one, taking
+// the frames from its handler up as the continuation to resume.
+fn perform(
+    op: String,
+    v: Value,
+    frames: &mut Vec<Frame>,
+) -> Result<State, String> {
+    let handles = |frame: &Frame| match frame {
+        Frame::Handle { clauses, .. } => clauses.iter().any(|c| c.op == op),
+        _ => false,
+    };
+    let Some(i) = frames.iter().rposition(handles) else {
+        return Err(format!("unhandled effect {op}"));
+    };
+    let captured = frames.split_off(i);
+    let Frame::Handle { clauses, nv, .. } = &captured[0] else {
+        unreachable!("the frame found is a handler");
+    };
+    let clause = clauses.iter().find(|c| c.op == op).unwrap();
+    let (body, mut nv) = (Rc::clone(&clause.body), nv.clone());
+    nv.insert(clause.var.clone(), v);
+    nv.insert(clause.k.clone(), Value::Resume { frames: captured });
+    Ok(State::Eval(body, nv))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{elaborate, interp, with_output};
+    use crate::parse::{parse_expression, parse_expression_with, tokenize};
+
+    fn parse(input: &str) -> Exp {
+        parse_expression(&tokenize(input).unwrap()).unwrap()
+    }
+
+    fn run(input: &str) -> Result<Value, String> {
+        eval(&parse(input), &Env::new())
+    }
+
+    // Run with the operations the effect examples declare.
+    fn run_effects(input: &str) -> Result<Value, String> {
+        let ops = Ops::from([
+            ("Get".to_string(), (Type::Unit, Type::Int)),
+            ("Put".to_string(), (Type::Int, Type::Unit)),
+            ("Yield".to_string(), (Type::Int, Type::Unit)),
+            ("Flip".to_string(), (Type::Unit, Type::Bool)),
+        ]);
+        let tokens = tokenize(input).unwrap();
+        let e = parse_expression_with(&tokens, &Aliases::new(), &ops);
+        let (e, _, _) = elaborate(&e.unwrap(), &TEnv::new())?;
+        eval(&e, &Env::new())
+    }
+
+    #[test]
+    fn agrees_with_interp_without_letcc() {
+        let inputs = [
+            "1 + -2 + 3",
+            "\"a\" ++ \"b\"",
+            "let x = 1 { let y = x + 1 { fn(z: int) { x + y + z } } }(3)",
+            "if 1 < 2 { 1.5 } else { 2.5 }",
+            "substring(\"hello\", 1, 3)",
+            "print(1); print(\"two\"); ()",
+            "fn(x: int) { x }",
+            "(1 + 2 : int)",
+            "9223372036854775807 + 1",
+            "if 0 { 1 } else { 2 }",
+            "1(2)",
+            "y",
+            "str->int(\"x\")",
+            "try { 1 + raise \"no\" } catch (e) { e ++ \"!\" }",
+            "try { substring(\"x\", 0, 2) } catch (e) { print(e); 0 }",
+            "try { 1 } catch (e) { raise e }",
+            "raise \"up\"",
+        ];
+        for input in inputs {
+            let e = parse(input);
+            assert_eq!(
+                with_output(|| eval(&e, &Env::new())),
+                with_output(|| interp(&e, &Env::new())),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn casts_agree_with_interp() {
+        let inputs = [
+            "fn(x: int) { x + 1 }((41 : dyn))",
+            "fn(x: int) { x + 1 }((\"2\" : dyn))",
+            "let f = (fn(x: int) { x + 1 } : dyn) { f(true) }",
+            "let f = (fn(x: int) { x } : dyn) { (f : (int -> int)) }(3)",
+            "try { (\"a\" : dyn) + 1 } catch (e) { 0 }",
+            "try { if true { (\"a\" : dyn) } else { 1 } } catch (e) { 0 }",
+        ];
+        for input in inputs {
+            let Ok((e, _, _)) = elaborate(&parse(input), &TEnv::new()) else {
+                continue;
+            };
+            assert_eq!(
+                eval(&e, &Env::new()),
+                interp(&e, &Env::new()),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn letcc_exits_early() {
+        // without a throw, letcc is its body's value
+        assert_eq!(run("letcc k: cont int { 1 + 2 }"), Ok(Value::Int(3)));
+        // throwing skips the rest of the body...
+        assert_eq!(
+            run("letcc k: cont int { 1 + k(10) + print(2) }"),
+            Ok(Value::Int(10))
+        );
+        // ...but not what comes after the letcc
+        assert_eq!(
+            run("1 + letcc k: cont int { if true { k(2) } else { 3 } + 100 }"),
+            Ok(Value::Int(3))
+        );
+        // nothing printed after the throw runs
+        assert_eq!(
+            with_output(|| run(
+                "letcc k: cont unit { print(1); k(()); print(2) }"
+            )),
+            (Ok(Value::Unit), "1\n".to_string())
+        );
+    }
+
+    #[test]
+    fn letcc_exits_nested_calls() {
+        // a continuation passed down into a function exits every call
+        // between it and the letcc
+        let search = "let find = fn(exit: cont str, n: int) {\n\
+                      let check = fn(i: int) { if i < n { () } else { exit(int->str(i)) } } {\n\
+                      check(1); check(2); check(3); \"none\" } } {\n\
+                      letcc k: cont str { find(k, 2) } ++ \"!\" }";
+        assert_eq!(run(search), Ok(Value::Str("2!".to_string())));
+        let search = search.replace("find(k, 2)", "find(k, 9)");
+        assert_eq!(run(&search), Ok(Value::Str("none!".to_string())));
+    }
+
+    #[test]
+    fn continuations_can_be_reentered() {
+        // the continuation escapes the letcc and is called again later,
+        // going back to the let with a new value
+        let again = "let f = letcc k: cont (int -> int) {\n\
+                     fn(n: int) { k(fn(m: int) { n }) } } { print(1); f(5) }";
+        assert_eq!(
+            with_output(|| run(again)),
+            (Ok(Value::Int(5)), "1\n1\n".to_string())
+        );
+    }
+
+    #[test]
+    fn interp_refuses_what_a_try_would_hide() {
+        let input = "try { letcc k: cont int { k(1) } } catch (e) { 0 }";
+        assert_eq!(run(input), Ok(Value::Int(1)));
+        assert_eq!(
+            interp(&parse(input), &Env::new()),
+            Err("letcc needs the CPS interpreter".to_string())
+        );
+    }
+
+    #[test]
+    fn continuations_keep_their_handlers() {
+        // throwing out of a try leaves its handler behind...
+        assert_eq!(
+            run("try { letcc k: cont int { try { k(1) } catch (e) { 2 } } + \
+                 raise \"x\" } catch (e) { 3 }"),
+            Ok(Value::Int(3))
+        );
+        // ...and re-entering one brings back the handlers around it: the
+        // second time through, g raises inside the try
+        let again = "let f = try { let g = letcc k: cont (int -> int) {\n\
+                     fn(n: int) { if n < 1 { n } else { k(fn(m: int) { raise \"no\" }) } } }\n\
+                     { g(0); g } } catch (e) { fn(n: int) { 7 } } { f(1) }";
+        assert_eq!(run(again), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn handlers_resume_performs() {
+        // state: the handler turns the body into a function of the state
+        let state = "let counter = handle (\n\
+                     perform Put(perform Get(()) + 1); perform Get(()) + 10\n\
+                     ) with {\n\
+                     Get(u, k) -> fn(s: int) { k(s)(s) };\n\
+                     Put(n, k) -> fn(s: int) { k(())(n) };\n\
+                     return x -> fn(s: int) { x } } { counter(5) }";
+        assert_eq!(run_effects(state), Ok(Value::Int(16)));
+        // generators: each yield hands a value out and waits to go on
+        let upto = "let upto = fn(me: dyn) { fn(n: int) {\n\
+                    if n < 1 { () } else { me(me)(n + -1); perform Yield(n) } } } {\n\
+                    handle upto(upto)(3) with {\n\
+                    Yield(n, k) -> int->str(n) ++ \" \" ++ k(());\n\
+                    return x -> \"done\" } }";
+        assert_eq!(run_effects(upto), Ok(Value::Str("1 2 3 done".to_string())));
+        // nondeterminism: resuming twice runs the rest of the body twice
+        let flips = "handle let a = perform Flip(()) { let b = perform Flip(()) {\n\
+                     if a { if b { \"tt \" } else { \"tf \" } }\n\
+                     else { if b { \"ft \" } else { \"ff \" } } } }\n\
+                     with { Flip(u, k) -> k(true) ++ k(false) }";
+        assert_eq!(
+            run_effects(flips),
+            Ok(Value::Str("tt tf ft ff ".to_string()))
+        );
+        // an operation goes to the nearest handler with a clause for it
+        let nested = "handle handle perform Get(()) + 1 with {\n\
+                      Yield(n, k) -> 0 } with { Get(u, k) -> k(10) }";
+        assert_eq!(run_effects(nested), Ok(Value::Int(11)));
+        assert_eq!(
+            run_effects("perform Get(())"),
+            Err("unhandled effect Get".to_string())
+        );
+    }
+
+    #[test]
+    fn tail_calls_take_no_frames() {
+        let count = "let count = fn(me: dyn) { fn(n: int) {\n\
+                     if n < 1 { (n : dyn) } else { me(me)(n + -1) } } } {\n\
+                     count(count)(1000000) }";
+        let (e, _, _) = elaborate(&parse(count), &TEnv::new()).unwrap();
+        assert_eq!(eval(&e, &Env::new()), Ok(Value::Int(0)));
+    }
+}
@@ -0,0 +1,312 @@
+use super::*;
+use parse::UNTERMINATED_COMMENT;
+use parse::parse_effect_declaration;
+use parse::parse_expression_with;
+use parse::parse_type_definition;
+use parse::tokenize_with_comments;
+use pretty::Printer;
+use repl::nesting;
+
+// A piece of a source file: a comment on lines of its own, or one REPL
+// entry (the lines the REPL would read before evaluating it).
+enum Item {
+    Comment(String),
+    Entry(Entry),
+}
+
+struct Entry {
+    // 1-based lines the entry starts and ends on
+    first: usize,
+    last: usize,
+    tokens: Vec<Token>,
+    // comments ahead of the entry on its first line
+    leading: Vec<String>,
+    // comments after the entry on its last line
+    trailing: Vec<String>,
+    // whether there are comments inside the entry, which can't be placed
+    // in a reprinted layout
+    commented: bool,
+}
+
+// Split a file the way the REPL reads it: an entry ends at the first line
+// that leaves its parentheses and braces balanced. Each item comes with
+// whether blank lines separated it from the one before.
+fn items(src: &str) -> Result<Vec<(bool, Item)>, String> {
+    let mut items = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut blank_before = false;
+    // a block comment still open at the end of a line, and where it began
+    let mut open_comment = String::new();
+    let mut open_line = 0;
+
+    for (i, line) in src.lines().enumerate() {
+        if line.trim().is_empty() && open_comment.is_empty() {
+            blank_before |= current.is_none();
+            continue;
+        }
+        // inside a block comment, keep the line's indentation
+        let text = if open_comment.is_empty() {
+            open_line = i + 1;
+            line.trim().to_string()
+        } else {
+            format!("{open_comment}{}", line.trim_end())
+        };
+        open_comment.clear();
+        let tokens = match tokenize_with_comments(&text) {
+            Ok(tokens) => tokens,
+            Err(msg) if msg == UNTERMINATED_COMMENT => {
+                open_comment = text + "\n";
+                continue;
+            }
+            Err(msg) => {
+                return Err(format!("line {}: Tokenizer error: {msg}", i + 1));
+            }
+        };
+
+        // split the line into comments before, between and after its code
+        let is_code = |t: &Token| !matches!(t, Token::Comment(_));
+        let Some(start) = tokens.iter().position(is_code) else {
+            match &mut current {
+                Some(entry) => entry.commented = true,
+                None => {
+                    for t in tokens {
+                        let blank = std::mem::take(&mut blank_before);
+                        items.push((blank, Item::Comment(t.to_string())));
+                    }
+                }
+            }
+            continue;
+        };
+        let end = tokens.iter().rposition(is_code).unwrap() + 1;
+        let texts = |ts: &[Token]| ts.iter().map(|t| t.to_string()).collect();
+
+        let entry = current.get_or_insert_with(|| Entry {
+            first: open_line,
+            last: 0,
+            tokens: Vec::new(),
+            leading: texts(&tokens[..start]),
+            trailing: Vec::new(),
+            commented: false,
+        });
+        if !entry.tokens.is_empty() {
+            entry.commented |= start > 0;
+        }
+        let code = &tokens[start..end];
+        entry.commented |= !code.iter().all(is_code);
+        entry.tokens.extend(code.iter().filter(|t| is_code(t)).cloned());
+        entry.last = i + 1;
+
+        if nesting(&entry.tokens) == 0 {
+            let mut entry = current.take().unwrap();
+            entry.trailing = texts(&tokens[end..]);
+            let blank = std::mem::take(&mut blank_before);
+            items.push((blank, Item::Entry(entry)));
+        } else {
+            entry.commented |= end < tokens.len();
+        }
+    }
+
+    if !open_comment.is_empty() {
+        return Err(format!("line {open_line}: {UNTERMINATED_COMMENT}"));
+    }
+    match current {
+        Some(entry) => Err(format!(
+            "line {}: unbalanced parentheses or braces",
+            entry.first
+        )),
+        None => Ok(items),
+    }
+}
+
+// Reprint every entry of `src` in canonical layout. Comments before and
+// after an entry stay with it; an entry with comments inside it is kept
+// as written. Blank lines between items are kept, collapsed to one.
+pub fn format_source(src: &str, printer: &Printer) -> Result<String, String> {
+    let lines: Vec<&str> = src.lines().collect();
+    let mut out = String::new();
+    let mut aliases = Aliases::new();
+    let mut ops = Ops::new();
+    for (blank_before, item) in items(src)? {
+        if blank_before && !out.is_empty() {
+            out.push('\n');
+        }
+        let entry = match item {
+            Item::Comment(text) => {
+                out.push_str(&text);
+                out.push('\n');
+                continue;
+            }
+            Item::Entry(entry) => entry,
+        };
+        let parse_error =
+            |msg| format!("line {}: Parse error: {msg}", entry.first);
+        // a declaration prints on one line, as the REPL shows it
+        let printed = if entry.tokens.first() == Some(&Token::Type) {
+            let (name, typ) =
+                parse_type_definition(&entry.tokens, &aliases, &ops)
+                    .map_err(parse_error)?;
+            let printed = format!("type {name} = {typ}");
+            aliases.insert(name, typ);
+            printed
+        } else if entry.tokens.first() == Some(&Token::Effect) {
+            let (name, (param, result)) =
+                parse_effect_declaration(&entry.tokens, &aliases, &ops)
+                    .map_err(parse_error)?;
+            let printed = format!("effect {name}: {param} -> {result}");
+            ops.insert(name, (param, result));
+            printed
+        } else {
+            let ast = parse_expression_with(&entry.tokens, &aliases, &ops)
+                .map_err(parse_error)?;
+            printer.print(&ast)
+        };
+        if entry.commented {
+            for line in &lines[entry.first - 1..entry.last] {
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+            continue;
+        }
+        for comment in &entry.leading {
+            out.push_str(comment);
+            out.push('\n');
+        }
+        out.push_str(&printed);
+        for comment in &entry.trailing {
+            out.push(' ');
+            out.push_str(comment);
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(src: &str) -> Result<String, String> {
+        format_source(src, &Printer::default())
+    }
+
+    #[test]
+    fn canonical_spacing() {
+        assert_eq!(format("1+2  ++3<x\n"), Ok("1 + 2 ++ 3 < x\n".to_string()));
+        assert_eq!(
+            format("fn (x:int){x+1}( 5 )"),
+            Ok("fn(x: int) { x + 1 }(5)\n".to_string())
+        );
+        assert_eq!(
+            format("let x=1{if x<2{x}else{ 2 }}"),
+            Ok("let x = 1 { if x < 2 { x } else { 2 } }\n".to_string())
+        );
+    }
+
+    #[test]
+    fn multi_line_entries_are_joined_or_reindented() {
+        let src = "let s = \"hi\" {\n        s ++ \" there\"\n}\n";
+        assert_eq!(
+            format(src),
+            Ok("let s = \"hi\" { s ++ \" there\" }\n".to_string())
+        );
+
+        let printer = Printer { indent: 2, width: 20 };
+        let src =
+            "let add = fn(a: int) { fn(b: int) {\na + b } } {\n add(3)(4) }";
+        assert_eq!(
+            format_source(src, &printer),
+            Ok("let add = fn(a: int) {\n  fn(b: int) {\n    a + b\n  }\n} {\n  add(3)(4)\n}\n"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn entries_and_blank_lines_are_kept() {
+        let src = "1\n2\n\n\n\n3\n";
+        assert_eq!(format(src), Ok("1\n2\n\n3\n".to_string()));
+        assert_eq!(format("\n\n1\n"), Ok("1\n".to_string()));
+        assert_eq!(format(""), Ok(String::new()));
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let printer = Printer { indent: 4, width: 24 };
+        let src = "let adder = fn(x:int) {fn(y:int) {x+y}} {let add8 = adder(8) {add8(2) + adder(4)(6)}}\nif true {\n\"yes\"\n} else {\n\"no\"\n}\n";
+        let once = format_source(src, &printer).unwrap();
+        assert_eq!(format_source(&once, &printer), Ok(once.clone()));
+    }
+
+    #[test]
+    fn long_top_level_sequences_stay_one_entry() {
+        let src = "print(\"one two three\"); print(\"four five six\"); \
+                   print(\"seven eight nine\")\n";
+        let printer = Printer { indent: 4, width: 40 };
+        let once = format_source(src, &printer).unwrap();
+        assert_eq!(
+            once,
+            "(print(\"one two three\");\nprint(\"four five six\");\n\
+             print(\"seven eight nine\"))\n"
+        );
+        assert_eq!(format_source(&once, &printer), Ok(once.clone()));
+    }
+
+    #[test]
+    fn comments_around_entries_are_kept() {
+        let src = "// header\n\n/* two\n   lines */\n1+2 // sum\n\n// footer\n";
+        assert_eq!(
+            format(src),
+            Ok("// header\n\n/* two\n   lines */\n1 + 2 // sum\n\n// footer\n"
+                .to_string())
+        );
+        assert_eq!(
+            format("/* a */ let x=1 {\nx } /* b */ // c\n"),
+            Ok("/* a */\nlet x = 1 { x } /* b */ // c\n".to_string())
+        );
+    }
+
+    #[test]
+    fn entries_with_comments_inside_are_kept_as_written() {
+        let entry = "let x = 1 {\n  // the body\n    x+1\n}\n";
+        assert_eq!(
+            format(&format!("{entry}2+3\n")),
+            Ok(format!("{entry}2 + 3\n"))
+        );
+        let src = "let x = 1 { // why\n  x }\nf(/* arg */ 1)\n";
+        assert_eq!(format(src), Ok(src.to_string()));
+    }
+
+    #[test]
+    fn type_declarations_are_kept() {
+        let src = "type  F=(int->int)\nlet f:F=fn(x:int){x} {f(1)}\n";
+        assert_eq!(
+            format(src),
+            Ok("type F = (int -> int)\nlet f: F = fn(x: int) { x } { f(1) }\n"
+                .to_string())
+        );
+        assert_eq!(
+            format("1\ntype F = G\n"),
+            Err("line 2: Parse error: Unknown type: G".to_string())
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(
+            format("1\n/* open\n\n"),
+            Err("line 2: unterminated comment".to_string())
+        );
+        assert_eq!(
+            format("1\n1 +\n"),
+            Err("line 2: Parse error: Expected a factor".to_string())
+        );
+        assert_eq!(
+            format("1\n#\n"),
+            Err("line 2: Tokenizer error: Unexpected character: '#'"
+                .to_string())
+        );
+        assert_eq!(
+            format("1\nlet x = 1 {\nx\n"),
+            Err("line 2: unbalanced parentheses or braces".to_string())
+        );
+    }
+}
@@ -1,181 +1,2174 @@
 use super::*;
+use std::cell::{Cell, RefCell};
 
+// How a function's argument, or a let's value, is bound to its variable.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Strategy {
+    // evaluated before the body runs
+    #[default]
+    ByValue,
+    // evaluated each time the variable is used, and not at all if unused
+    ByName,
+    // evaluated the first time the variable is used, then remembered
+    ByNeed,
+}
+
+// Evaluate call-by-value.
 pub fn interp(e: &Exp, nv: &Env) -> Result<Value, String> {
-    match e {
-        Exp::Int(n) => Ok(Value::Int(*n)),
-        Exp::Bool(b) => Ok(Value::Bool(*b)),
-        Exp::Str(s) => Ok(Value::Str(s.clone())),
+    interp_with(e, nv, Strategy::ByValue)
+}
 
-        Exp::Var(var) => match nv.get(var) {
-            Some(v) => Ok(v.clone()),
-            None => Err(format!("{var} not bound")),
-        },
+// Evaluate binding variables as `strategy` says. Thunks only ever sit in
+// environments: looking up a variable forces its thunk, so the value
+// returned is never one.
+pub fn interp_with(
+    e: &Exp,
+    nv: &Env,
+    strategy: Strategy,
+) -> Result<Value, String> {
+    eval(e, nv, strategy).map_err(|(Stop::Raise(msg) | Stop::Halt(msg))| msg)
+}
 
-        Exp::Plus { left, right } => {
-            let l_val = interp(left, nv)?;
-            let r_val = interp(right, nv)?;
-            match (l_val, r_val) {
-                (Value::Int(l), Value::Int(r)) => Ok(Value::Int(l + r)),
-                (l, r) => Err(format!(
-                    "+ expects two integers, got {:?} + {:?}", // Use Debug format
-                    l, r
-                )),
-            }
+// Why evaluation stopped short of a value.
+enum Stop {
+    // an exception, raised or a runtime error's message, which a try
+    // catches
+    Raise(String),
+    // a program this interpreter can't run, such as one with a letcc;
+    // a try doesn't catch it, as the CPS interpreter wouldn't have failed
+    Halt(String),
+}
+
+impl From<String> for Stop {
+    fn from(msg: String) -> Stop {
+        Stop::Raise(msg)
+    }
+}
+
+// Deepest `eval` recurses. Tail positions don't count, as they loop, but
+// dyn lets a well-typed program recurse without end elsewhere; it stops
+// here rather than overflowing the stack.
+pub const MAX_DEPTH: usize = 3_000;
+
+// Stack for a thread that evaluates: room for MAX_DEPTH levels even in an
+// unoptimized build, which takes some 18 KiB a level.
+pub const STACK_SIZE: usize = 256 << 20;
+
+thread_local! {
+    // How deep `eval` is recursing.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// A level of `eval`'s recursion, counted in DEPTH while it lasts.
+struct Depth;
+
+impl Depth {
+    fn enter() -> Result<Depth, Stop> {
+        let depth = DEPTH.get();
+        if depth >= MAX_DEPTH {
+            return Err(Stop::Halt(format!(
+                "evaluation nested more than {MAX_DEPTH} levels deep"
+            )));
         }
+        DEPTH.set(depth + 1);
+        Ok(Depth)
+    }
+}
 
-        Exp::Concat { left, right } => {
-            let l_val = interp(left, nv)?;
-            let r_val = interp(right, nv)?;
-            match (l_val, r_val) {
-                (Value::Str(l), Value::Str(r)) => {
-                    Ok(Value::Str(format!("{l}{r}")))
+impl Drop for Depth {
+    fn drop(&mut self) {
+        DEPTH.set(DEPTH.get() - 1);
+    }
+}
+
+fn eval(e: &Exp, nv: &Env, strategy: Strategy) -> Result<Value, Stop> {
+    let _depth = Depth::enter()?;
+    let interp = |e: &Exp, nv: &Env| eval(e, nv, strategy);
+    // An expression in tail position is evaluated by going round this loop
+    // with `e` and `nv` replaced rather than by recursing, so that tail
+    // calls run in constant stack. A called closure's body and a let's
+    // environment are owned here, by `body` and `body_nv`.
+    let (mut e, mut nv) = (e, nv);
+    let mut body: Rc<Exp>;
+    let mut body_nv: Env;
+    loop {
+        return match e {
+            Exp::Int(n) => Ok(Value::Int(*n)),
+            Exp::Float(x) => Ok(Value::Float(*x)),
+            Exp::Bool(b) => Ok(Value::Bool(*b)),
+            Exp::Str(s) => Ok(Value::Str(s.clone())),
+            Exp::Unit => Ok(Value::Unit),
+
+            // primitives are found after every binding, so they can be
+            // shadowed
+            Exp::Var(var) => match nv.get(var) {
+                Some(v) => force(v, strategy),
+                None if prims::prim_type(var).is_some() => {
+                    Ok(Value::Prim { name: var.clone(), args: vec![] })
                 }
-                (l, r) => Err(format!(
-                    "++ expects two strings, got {:?} ++ {:?}", // Match operator
-                    l, r
-                )),
+                None => Err(format!("{var} not bound").into()),
+            },
+
+            Exp::Plus { left, right } => {
+                Ok(plus(interp(left, nv)?, interp(right, nv)?)?)
             }
-        }
 
-        Exp::LessThan { left, right } => {
-            let l_val = interp(left, nv)?;
-            let r_val = interp(right, nv)?;
-            match (l_val, r_val) {
-                (Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l < r)),
-                (l, r) => Err(format!(
-                    "< expects two integers, got {:?} < {:?}", // Match operator
-                    l, r
-                )),
+            Exp::Concat { left, right } => {
+                Ok(concat(interp(left, nv)?, interp(right, nv)?)?)
+            }
+
+            Exp::LessThan { left, right } => {
+                Ok(less_than(interp(left, nv)?, interp(right, nv)?)?)
             }
+
+            Exp::Cnd { tst, thn, els } => {
+                let tst_val = interp(tst, nv)?;
+                match tst_val {
+                    Value::Bool(true) => e = thn,
+                    Value::Bool(false) => e = els,
+                    v => {
+                        return Err(
+                            format!("boolean expected, found {:?}", v).into()
+                        );
+                    } // Use Debug format
+                }
+                continue;
+            }
+
+            Exp::Let1 { var, value, body: let_body } => {
+                let val = bind(value, nv, strategy)?;
+                let mut new_nv = nv.clone();
+                new_nv.insert(var.clone(), val);
+                body_nv = new_nv;
+                (e, nv) = (let_body, &body_nv);
+                continue;
+            }
+
+            // Corrected to match Exp::Lam definition in main.rs
+            Exp::Lam { var, var_type, body } => Ok(Value::Fun {
+                var: var.clone(),
+                // an unannotated parameter was never checked
+                var_type: var_type.clone().unwrap_or(Type::Dyn),
+                body: Rc::clone(body),
+                nv: nv.clone(),
+            }),
+
+            Exp::App { fun, arg } => {
+                let fun_val = interp(fun, nv)?;
+                // primitives are strict whatever the strategy
+                let arg_val = match fun_val {
+                    Value::Fun { .. } => bind(arg, nv, strategy)?,
+                    _ => interp(arg, nv)?,
+                };
+
+                match fun_val {
+                    // Corrected to match Value::Fun definition
+                    Value::Fun {
+                        var, body: fun_body, nv: closure_nv, ..
+                    } => {
+                        let mut new_nv = closure_nv;
+                        new_nv.insert(var, arg_val);
+                        (body, body_nv) = (fun_body, new_nv);
+                        (e, nv) = (&body, &body_nv);
+                        continue;
+                    }
+                    Value::Prim { name, args } => {
+                        Ok(prims::apply(&name, &args, arg_val)?)
+                    }
+                    v => {
+                        Err(format!("function expected, found {:?}", v).into())
+                    } // Use Debug format
+                }
+            }
+
+            Exp::Seq { first, second } => {
+                interp(first, nv)?;
+                e = second;
+                continue;
+            }
+
+            Exp::Print { arg } => Ok(print(interp(arg, nv)?)?),
+
+            // types are only checked statically
+            Exp::Ascribe { exp, .. } | Exp::At { exp, .. } => {
+                e = exp;
+                continue;
+            }
+
+            Exp::LetCC { .. } => {
+                Err(Stop::Halt("letcc needs the CPS interpreter".to_string()))
+            }
+
+            // an exception is carried back as an error, so that runtime
+            // errors can be caught as well, with their message
+            Exp::Raise { arg } => Err(Stop::Raise(raise(interp(arg, nv)?))),
+
+            // the argument is evaluated first, as the CPS interpreter does
+            Exp::Perform { arg, .. } => {
+                interp(arg, nv)?;
+                Err(Stop::Halt("perform needs the CPS interpreter".to_string()))
+            }
+
+            Exp::Cast { exp, from, to, label } => {
+                Ok(cast(interp(exp, nv)?, from, to, label)?)
+            }
+
+            // with no perform to handle, only the return clause is left
+            Exp::Handle { body: handled, ret, .. } => {
+                let v = interp(handled, nv)?;
+                let Some(Return { var, body: ret_body }) = ret else {
+                    return Ok(v);
+                };
+                let mut new_nv = nv.clone();
+                new_nv.insert(var.clone(), v);
+                body_nv = new_nv;
+                (e, nv) = (ret_body, &body_nv);
+                continue;
+            }
+
+            Exp::Try { body: try_body, var, handler } => {
+                match interp(try_body, nv) {
+                    Err(Stop::Raise(msg)) => {
+                        let mut new_nv = nv.clone();
+                        new_nv.insert(var.clone(), Value::Str(msg));
+                        body_nv = new_nv;
+                        (e, nv) = (handler, &body_nv);
+                        continue;
+                    }
+                    result => result,
+                }
+            }
+        };
+    }
+}
+
+// The value to bind to a variable for `e`: its value, or under a lazy
+// strategy a thunk to compute it later.
+fn bind(e: &Rc<Exp>, nv: &Env, strategy: Strategy) -> Result<Value, Stop> {
+    match (strategy, &**e) {
+        (Strategy::ByValue, _) => eval(e, nv, strategy),
+        // share the variable's binding rather than wrap it in another thunk
+        (_, Exp::Var(var)) if nv.contains_key(var) => Ok(nv[var].clone()),
+        _ => Ok(Value::Thunk {
+            exp: Rc::clone(e),
+            nv: nv.clone(),
+            cache: Rc::new(RefCell::new(None)),
+        }),
+    }
+}
+
+// The value of a variable's binding, computing it if it is a thunk.
+fn force(v: &Value, strategy: Strategy) -> Result<Value, Stop> {
+    let Value::Thunk { exp, nv, cache } = v else {
+        return Ok(v.clone());
+    };
+    if let Some(v) = &*cache.borrow() {
+        return Ok(v.clone());
+    }
+    let v = eval(exp, nv, strategy)?;
+    if strategy == Strategy::ByNeed {
+        *cache.borrow_mut() = Some(v.clone());
+    }
+    Ok(v)
+}
+
+thread_local! {
+    // Where print writes while `with_output` is collecting it.
+    static OUTPUT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Run `f`, collecting everything print writes instead of sending it to
+// stdout. Returns what `f` returned and the text printed.
+pub fn with_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let outer = OUTPUT.replace(Some(String::new()));
+    let result = f();
+    let printed = OUTPUT.replace(outer).unwrap_or_default();
+    (result, printed)
+}
+
+// Write a value and a newline, to stdout or the innermost `with_output`.
+pub fn print(v: Value) -> Result<Value, String> {
+    let line = format!("{v}\n");
+    OUTPUT.with_borrow_mut(|output| match output {
+        Some(text) => text.push_str(&line),
+        None => std::print!("{line}"),
+    });
+    Ok(Value::Unit)
+}
+
+// The operators on values, shared with the CPS interpreter. They live
+// outside `interp` to keep its stack frame small, since it recurses once
+// per level of nesting.
+
+pub fn plus(l_val: Value, r_val: Value) -> Result<Value, String> {
+    match (l_val, r_val) {
+        (Value::Int(l), Value::Int(r)) => match l.checked_add(r) {
+            Some(n) => Ok(Value::Int(n)),
+            None => Err(format!("integer overflow in {l} + {r}")),
+        },
+        (Value::Float(l), Value::Float(r)) => Ok(Value::Float(l + r)),
+        (l, r) => Err(format!(
+            "+ expects two integers or two floats, got {:?} + {:?}", // Use Debug format
+            l, r
+        )),
+    }
+}
+
+pub fn concat(l_val: Value, r_val: Value) -> Result<Value, String> {
+    match (l_val, r_val) {
+        (Value::Str(l), Value::Str(r)) => Ok(Value::Str(format!("{l}{r}"))),
+        (l, r) => Err(format!(
+            "++ expects two strings, got {:?} ++ {:?}", // Match operator
+            l, r
+        )),
+    }
+}
+
+pub fn less_than(l_val: Value, r_val: Value) -> Result<Value, String> {
+    match (l_val, r_val) {
+        (Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l < r)),
+        (Value::Float(l), Value::Float(r)) => Ok(Value::Bool(l < r)),
+        (l, r) => Err(format!(
+            "< expects two integers or two floats, got {:?} < {:?}", // Match operator
+            l, r
+        )),
+    }
+}
+
+// The exception raised by `raise v`.
+pub fn raise(v: Value) -> String {
+    match v {
+        Value::Str(msg) => msg,
+        v => format!("raise expects a str, found {:?}", v),
+    }
+}
+
+// A value of type `from` as a `to`: itself once it is checked to be one,
+// and if it is a function, wrapped to cast its argument and result too.
+pub fn cast(
+    v: Value,
+    from: &Type,
+    to: &Type,
+    label: &str,
+) -> Result<Value, String> {
+    let is_a = match (to.resolve(), &v) {
+        // every value is a top
+        (Type::Dyn | Type::Top, _) => return Ok(v),
+        (Type::Int, Value::Int(_))
+        | (Type::Float, Value::Float(_))
+        | (Type::Bool, Value::Bool(_))
+        | (Type::Str, Value::Str(_))
+        | (Type::IntOption, Value::IntOption(_))
+        | (Type::Unit, Value::Unit)
+        | (Type::Fun { .. }, Value::Fun { .. } | Value::Prim { .. })
+        | (Type::Cont { .. }, Value::Cont { .. } | Value::Resume { .. }) => {
+            true
         }
+        _ => false,
+    };
+    if !is_a {
+        return Err(format!(
+            "cast from {from} to {to} failed at {label}: got {}",
+            kind(&v)
+        ));
+    }
+    if !matches!(to.resolve(), Type::Fun { .. }) || from == to {
+        return Ok(v);
+    }
+    let param = match &v {
+        Value::Fun { var_type, .. } => Some(var_type),
+        _ => None,
+    };
+    let fun = cast_wrapper(Exp::Var("f".to_string()), param, from, to, label);
+    interp(&fun, &Env::from([("f".to_string(), v)]))
+}
 
-        Exp::Cnd { tst, thn, els } => {
-            let tst_val = interp(tst, nv)?;
-            match tst_val {
-                Value::Bool(true) => interp(thn, nv),
-                Value::Bool(false) => interp(els, nv),
-                v => Err(format!("boolean expected, found {:?}", v)), // Use Debug format
+// A function that calls `fun`, of type `from`, with its argument cast to
+// the parameter type `fun` has and its result cast to the one `to` has.
+// If `from` is dyn, `param` is the parameter type `fun` was written with,
+// if it is a fn. Where the types agree there is no cast, so that a call
+// in tail position stays one.
+pub fn cast_wrapper(
+    fun: Exp,
+    param: Option<&Type>,
+    from: &Type,
+    to: &Type,
+    label: &str,
+) -> Exp {
+    let parts = |t: &Type, param: Option<&Type>| match t.resolve() {
+        Type::Fun { param, result, .. } => {
+            ((**param).clone(), (**result).clone())
+        }
+        _ => (param.cloned().unwrap_or(Type::Dyn), Type::Dyn),
+    };
+    let (from_param, from_result) = parts(from, param);
+    let (to_param, to_result) = parts(to, None);
+    let cast = |exp, from: &Type, to: &Type| {
+        if from == to {
+            return exp;
+        }
+        Exp::Cast {
+            exp: Rc::new(exp),
+            from: Box::new(from.clone()),
+            to: Box::new(to.clone()),
+            label: label.to_string(),
+        }
+    };
+    let arg = cast(Exp::Var("x".to_string()), &to_param, &from_param);
+    let call = Exp::App { fun: Rc::new(fun), arg: Rc::new(arg) };
+    Exp::Lam {
+        var: "x".to_string(),
+        var_type: Some(to_param.clone()),
+        body: Rc::new(cast(call, &from_result, &to_result)),
+    }
+}
+
+// What kind of value `v` is, for cast errors.
+fn kind(v: &Value) -> &'static str {
+    match v {
+        Value::Int(_) => "int",
+        Value::Float(_) => "float",
+        Value::Bool(_) => "bool",
+        Value::Str(_) => "str",
+        Value::IntOption(_) => "int?",
+        Value::Unit => "unit",
+        Value::Fun { .. } | Value::Prim { .. } => "function",
+        Value::Thunk { .. } => "thunk",
+        Value::Cont { .. } | Value::Resume { .. } => "continuation",
+    }
+}
+
+// Whether a value of type `t` can be used where one of type `expected` is
+// wanted: `t` is a subtype of it. If `gradual`, dyn is taken for whatever
+// it is compared with, for a cast to check at run time. The error says
+// which component of the types fails, or is None if they differ outright.
+fn subtype(
+    t: &Type,
+    expected: &Type,
+    gradual: bool,
+) -> Result<(), Option<String>> {
+    // the component called `name`, a `t` wanted as an `expected`, fails
+    let within = |name: &str, t: &Type, expected: &Type, why: Option<_>| {
+        let why = why
+            .unwrap_or_else(|| format!("{t} is not a subtype of {expected}"));
+        Some(format!("in the {name}, {why}"))
+    };
+    match (t.resolve(), expected.resolve()) {
+        (Type::Dyn, _) | (_, Type::Dyn) if gradual => Ok(()),
+        // no value of never ever arrives, and every value is a top
+        (Type::Never, _) | (_, Type::Top) => Ok(()),
+        // a function will do for one that takes less and returns more, or
+        // performs fewer operations
+        (
+            Type::Fun { param, result, effects },
+            Type::Fun {
+                param: expected_param,
+                result: expected_result,
+                effects: expected_effects,
+            },
+        ) => {
+            subtype(expected_param, param, gradual).map_err(|why| {
+                within("parameter", expected_param, param, why)
+            })?;
+            subtype(result, expected_result, gradual).map_err(|why| {
+                within("result", result, expected_result, why)
+            })?;
+            let extra: Vec<&str> = effects
+                .difference(expected_effects)
+                .map(String::as_str)
+                .collect();
+            match extra.len() {
+                0 => Ok(()),
+                1 => Err(Some(format!(
+                    "in the effects, {} is not allowed",
+                    extra[0]
+                ))),
+                _ => Err(Some(format!(
+                    "in the effects, {} are not allowed",
+                    extra.join(", ")
+                ))),
             }
         }
+        // and a continuation for one that is thrown less
+        (Type::Cont { param }, Type::Cont { param: expected_param }) => {
+            subtype(expected_param, param, gradual).map_err(|why| {
+                within("continuation's parameter", expected_param, param, why)
+            })
+        }
+        _ if t == expected => Ok(()),
+        _ => Err(None),
+    }
+}
 
-        Exp::Let1 { var, value, body } => {
-            let val = interp(value, nv)?;
-            let mut new_nv = nv.clone();
-            new_nv.insert(var.clone(), val);
-            interp(body, &new_nv)
+// Whether a value of type `t` can be used where one of type `expected` is
+// wanted as it is.
+pub fn fits(t: &Type, expected: &Type) -> bool {
+    subtype(t, expected, false).is_ok()
+}
+
+// `message`, the error for a `t` wanted as an `expected`, followed by the
+// component of the types that fails if it is inside them.
+fn mismatch(
+    message: String,
+    t: &Type,
+    expected: &Type,
+    gradual: bool,
+) -> String {
+    match subtype(t, expected, gradual) {
+        Err(Some(why)) => format!("{message}: {why}"),
+        _ => message,
+    }
+}
+
+// The type of an expression that is one of two others, such as a
+// conditional: the least type both are subtypes of, top if they are
+// unrelated. None if dyn makes them only consistent, so that `meet` can
+// say what they agree on.
+fn join(l: &Type, r: &Type) -> Option<Type> {
+    if fits(l, r) {
+        return Some(r.clone());
+    } else if fits(r, l) {
+        return Some(l.clone());
+    }
+    match (l.resolve(), r.resolve()) {
+        (
+            Type::Fun { param, result, effects },
+            Type::Fun { param: r_param, result: r_result, effects: r_effects },
+        ) => Some(Type::Fun {
+            param: Box::new(common_subtype(param, r_param)?),
+            result: Box::new(join(result, r_result)?),
+            effects: effects.union(r_effects).cloned().collect(),
+        }),
+        (Type::Cont { param }, Type::Cont { param: r_param }) => {
+            Some(Type::Cont {
+                param: Box::new(common_subtype(param, r_param)?),
+            })
         }
+        _ => None,
+    }
+    .or_else(|| unrelated(l, r).then_some(Type::Top))
+}
 
-        // Corrected to match Exp::Lam definition in main.rs
-        Exp::Lam { var, var_type, body } => Ok(Value::Fun {
-            var: var.clone(),
-            var_type: var_type.clone(), // Store type in closure
-            body: body.clone(),
-            nv: nv.clone(),
+// The greatest type that is a subtype of both `l` and `r`, for the
+// parameter of a join: never if they are unrelated.
+fn common_subtype(l: &Type, r: &Type) -> Option<Type> {
+    if fits(l, r) {
+        return Some(l.clone());
+    } else if fits(r, l) {
+        return Some(r.clone());
+    }
+    match (l.resolve(), r.resolve()) {
+        (
+            Type::Fun { param, result, effects },
+            Type::Fun { param: r_param, result: r_result, effects: r_effects },
+        ) => Some(Type::Fun {
+            param: Box::new(join(param, r_param)?),
+            result: Box::new(common_subtype(result, r_result)?),
+            effects: effects.intersection(r_effects).cloned().collect(),
         }),
+        (Type::Cont { param }, Type::Cont { param: r_param }) => {
+            Some(Type::Cont { param: Box::new(join(param, r_param)?) })
+        }
+        _ => None,
+    }
+    .or_else(|| unrelated(l, r).then_some(Type::Never))
+}
 
-        Exp::App { fun, arg } => {
-            let fun_val = interp(fun, nv)?;
-            let arg_val = interp(arg, nv)?;
+// Whether neither of `l` and `r` could be used as the other even with
+// dyn checked at run time.
+fn unrelated(l: &Type, r: &Type) -> bool {
+    !consistent(l, r) && !consistent(r, l)
+}
 
-            match fun_val {
-                // Corrected to match Value::Fun definition
-                Value::Fun { var, body, nv: closure_nv, .. } => {
-                    let mut new_nv = closure_nv.clone();
-                    new_nv.insert(var, arg_val);
-                    interp(&body, &new_nv)
-                }
-                v => Err(format!("function expected, found {:?}", v)), // Use Debug format
+// Bind the effect variables in `param` to the effects `arg` has in their
+// place, so that calling a function that takes a function has the
+// effects of the one passed.
+fn bind_effects(
+    param: &Type,
+    arg: &Type,
+    bound: &mut HashMap<String, EffectRow>,
+) {
+    match (param.resolve(), arg.resolve()) {
+        (
+            Type::Fun { param, result, effects },
+            Type::Fun {
+                param: arg_param,
+                result: arg_result,
+                effects: arg_effects,
+            },
+        ) => {
+            bind_effects(param, arg_param, bound);
+            bind_effects(result, arg_result, bound);
+            let rest = arg_effects.difference(effects);
+            for var in effects.iter().filter(|e| is_effect_var(e)) {
+                bound
+                    .entry(var.clone())
+                    .or_default()
+                    .extend(rest.clone().cloned());
+            }
+        }
+        (Type::Cont { param }, Type::Cont { param: arg_param }) => {
+            bind_effects(param, arg_param, bound)
+        }
+        _ => {}
+    }
+}
+
+// `t` with its bound effect variables replaced by their effects.
+fn subst_effects(t: &Type, bound: &HashMap<String, EffectRow>) -> Type {
+    match t {
+        _ if bound.is_empty() => t.clone(),
+        Type::Fun { param, result, effects } => Type::Fun {
+            param: Box::new(subst_effects(param, bound)),
+            result: Box::new(subst_effects(result, bound)),
+            effects: subst_row(effects, bound),
+        },
+        Type::Cont { param } => {
+            Type::Cont { param: Box::new(subst_effects(param, bound)) }
+        }
+        // keep the alias's name unless it mentions a bound variable
+        Type::Alias { def, .. } => match subst_effects(def, bound) {
+            t_def if t_def == **def => t.clone(),
+            t_def => t_def,
+        },
+        _ => t.clone(),
+    }
+}
+
+fn subst_row(row: &EffectRow, bound: &HashMap<String, EffectRow>) -> EffectRow {
+    let mut effects = EffectRow::new();
+    for e in row {
+        match bound.get(e) {
+            Some(bound_effects) => {
+                effects.extend(bound_effects.iter().cloned())
+            }
+            None => {
+                effects.insert(e.clone());
             }
         }
     }
+    effects
+}
+
+// Whether a value of type `t` can be used where one of type `expected` is
+// wanted once checked at run time: it is a subtype wherever neither has
+// dyn.
+fn consistent(t: &Type, expected: &Type) -> bool {
+    subtype(t, expected, true).is_ok()
+}
+
+// The type of an expression that is one of two consistent others: where
+// one has dyn, whatever the other has there.
+fn meet(l: &Type, r: &Type) -> Type {
+    match (l.resolve(), r.resolve()) {
+        (Type::Dyn, _) => r.clone(),
+        (_, Type::Dyn) => l.clone(),
+        (
+            Type::Fun { param, result, effects },
+            Type::Fun { param: r_param, result: r_result, effects: r_effects },
+        ) => Type::Fun {
+            param: Box::new(meet(param, r_param)),
+            result: Box::new(meet(result, r_result)),
+            effects: effects.union(r_effects).cloned().collect(),
+        },
+        (Type::Cont { param }, Type::Cont { param: r_param }) => {
+            Type::Cont { param: Box::new(meet(param, r_param)) }
+        }
+        _ => join(l, r).unwrap_or_else(|| l.clone()),
+    }
+}
+
+// The number type an operator on an `l` and an `r` works on: the one both
+// are, or the one either is where the other is dyn or never, since never
+// returns. Int if dyn leaves it open, and never if both never return.
+fn operand_type(l: &Type, r: &Type) -> Option<Type> {
+    match (l.resolve(), r.resolve()) {
+        (Type::Never, Type::Never) => Some(Type::Never),
+        (t @ (Type::Int | Type::Float), other)
+        | (other, t @ (Type::Int | Type::Float))
+            if other == t || matches!(other, Type::Never | Type::Dyn) =>
+        {
+            Some(t.clone())
+        }
+        (Type::Never | Type::Dyn, Type::Never | Type::Dyn) => Some(Type::Int),
+        _ => None,
+    }
+}
+
+// `e`, the elaborated `source`, used as a `to`: cast to one, blaming
+// `source`, if its type `from` is only consistent with it. The blame is
+// where `source` was read, or what it says if the parser didn't record
+// that.
+fn coerce(e: Exp, source: &Exp, from: &Type, to: &Type) -> Exp {
+    if fits(from, to) {
+        return e;
+    }
+    let label = match source {
+        Exp::At { pos, .. } => pos.to_string(),
+        _ => pretty::flat(source),
+    };
+    Exp::Cast {
+        exp: Rc::new(e),
+        from: Box::new(from.clone()),
+        to: Box::new(to.clone()),
+        label,
+    }
 }
 
 // Type Checker function
 pub fn tc(e: &Exp, tnv: &TEnv) -> Result<Type, String> {
+    tc_effects(e, tnv).map(|(t, _)| t)
+}
+
+// The type of `e` and the effect operations evaluating it may perform.
+pub fn tc_effects(e: &Exp, tnv: &TEnv) -> Result<(Type, EffectRow), String> {
+    elaborate(e, tnv).map(|(_, t, effects)| (t, effects))
+}
+
+// `e` with a cast inserted wherever a value is used as a type it is only
+// consistent with, along with its type and effects. Evaluating the result
+// checks at run time what dyn kept the checker from knowing.
+pub fn elaborate(
+    e: &Exp,
+    tnv: &TEnv,
+) -> Result<(Exp, Type, EffectRow), String> {
+    let mut effects = EffectRow::new();
+    let (t, e) = synth(e, tnv, &mut effects)?;
+    Ok((e, t, effects))
+}
+
+// The type checker in synthesis mode, which finds the type of `e` from
+// `e` alone, adding its effects to `effects` and giving its type and its
+// elaboration.
+fn synth(
+    e: &Exp,
+    tnv: &TEnv,
+    effects: &mut EffectRow,
+) -> Result<(Type, Exp), String> {
+    infer(e, None, tnv, effects)
+}
+
+// The type checker in checking mode, for an `e` wanted as an `expected`:
+// an unannotated fn in it takes its parameter type from there. Whoever
+// wants it still compares the type found with `expected`, and casts.
+fn check(
+    e: &Exp,
+    expected: &Type,
+    tnv: &TEnv,
+    effects: &mut EffectRow,
+) -> Result<(Type, Exp), String> {
+    infer(e, Some(expected), tnv, effects)
+}
+
+// The type checker proper, in checking mode if there is an `expected`
+// type and in synthesis mode if not. Only a fn needs the expected type;
+// the other expressions just pass it on to the parts that give their
+// value.
+fn infer(
+    e: &Exp,
+    expected: Option<&Type>,
+    tnv: &TEnv,
+    effects: &mut EffectRow,
+) -> Result<(Type, Exp), String> {
     match e {
-        Exp::Int(_) => Ok(Type::Int),
-        Exp::Bool(_) => Ok(Type::Bool),
-        Exp::Str(_) => Ok(Type::Str),
+        Exp::Int(_) => Ok((Type::Int, e.clone())),
+        Exp::Float(_) => Ok((Type::Float, e.clone())),
+        Exp::Bool(_) => Ok((Type::Bool, e.clone())),
+        Exp::Str(_) => Ok((Type::Str, e.clone())),
+        Exp::Unit => Ok((Type::Unit, e.clone())),
 
         Exp::Var(var) => match tnv.get(var) {
-            Some(t) => Ok(t.clone()),
-            None => Err(format!("no known type for {var}")),
+            Some(t) => Ok((t.clone(), e.clone())),
+            None => match prims::prim_type(var) {
+                Some(t) => Ok((t, e.clone())),
+                None => Err(format!("no known type for {var}")),
+            },
         },
 
-        Exp::Plus { left, right } => {
-            let l_type = tc(left, tnv)?;
-            let r_type = tc(right, tnv)?;
-            match (&l_type, &r_type) {
-                (Type::Int, Type::Int) => Ok(Type::Int),
-                _ => Err("not both integers".to_string()),
-            }
+        // a dyn operand is cast to the type the other gives, or to an int
+        // if neither gives one
+        Exp::Plus { left: l_source, right: r_source } => {
+            let (l_type, left) = synth(l_source, tnv, effects)?;
+            let (r_type, right) = synth(r_source, tnv, effects)?;
+            let Some(t) = operand_type(&l_type, &r_type) else {
+                return Err(match l_type.resolve() {
+                    // no coercion: an int and a float don't mix
+                    Type::Float => "not both floats".to_string(),
+                    _ => "not both integers".to_string(),
+                });
+            };
+            let left = Rc::new(coerce(left, l_source, &l_type, &t));
+            let right = Rc::new(coerce(right, r_source, &r_type, &t));
+            Ok((t, Exp::Plus { left, right }))
         }
 
-        Exp::Concat { left, right } => {
-            let l_type = tc(left, tnv)?;
-            let r_type = tc(right, tnv)?;
-            match (&l_type, &r_type) {
-                // This is the line that was cut off:
-                (Type::Str, Type::Str) => Ok(Type::Str),
-                _ => Err("not both strings".to_string()),
+        Exp::Concat { left: l_source, right: r_source } => {
+            let (l_type, left) = synth(l_source, tnv, effects)?;
+            let (r_type, right) = synth(r_source, tnv, effects)?;
+            if !consistent(&l_type, &Type::Str)
+                || !consistent(&r_type, &Type::Str)
+            {
+                return Err("not both strings".to_string());
             }
+            let left = Rc::new(coerce(left, l_source, &l_type, &Type::Str));
+            let right = Rc::new(coerce(right, r_source, &r_type, &Type::Str));
+            Ok((Type::Str, Exp::Concat { left, right }))
         }
 
-        Exp::LessThan { left, right } => {
-            let l_type = tc(left, tnv)?;
-            let r_type = tc(right, tnv)?;
-            match (&l_type, &r_type) {
-                (Type::Int, Type::Int) => Ok(Type::Bool),
-                _ => Err("not both numbers".to_string()),
-            }
+        Exp::LessThan { left: l_source, right: r_source } => {
+            let (l_type, left) = synth(l_source, tnv, effects)?;
+            let (r_type, right) = synth(r_source, tnv, effects)?;
+            let Some(t) = operand_type(&l_type, &r_type) else {
+                return Err("not both numbers".to_string());
+            };
+            let left = Rc::new(coerce(left, l_source, &l_type, &t));
+            let right = Rc::new(coerce(right, r_source, &r_type, &t));
+            Ok((Type::Bool, Exp::LessThan { left, right }))
         }
 
-        Exp::Cnd { tst, thn, els } => {
-            let tst_type = tc(tst, tnv)?;
-            if tst_type != Type::Bool {
+        // branches of consistent types are cast to what both agree on
+        Exp::Cnd { tst: tst_source, thn: thn_source, els: els_source } => {
+            let (tst_type, tst) = synth(tst_source, tnv, effects)?;
+            if !consistent(&tst_type, &Type::Bool) {
                 return Err("condition must be a bool".to_string());
             }
-            let thn_type = tc(thn, tnv)?;
-            let els_type = tc(els, tnv)?;
-            if thn_type == els_type {
-                Ok(thn_type)
-            } else {
-                Err("then and else branches have different types".to_string())
-            }
+            let tst = coerce(tst, tst_source, &tst_type, &Type::Bool);
+            let (thn_type, thn) = infer(thn_source, expected, tnv, effects)?;
+            let (els_type, els) = infer(els_source, expected, tnv, effects)?;
+            // checked branches need only both fit where they are wanted
+            let wanted =
+                expected.filter(|t| fits(&thn_type, t) && fits(&els_type, t));
+            let t = match (join(&thn_type, &els_type), wanted) {
+                (Some(t), _) => t,
+                (None, Some(t)) => t.clone(),
+                (None, None) => meet(&thn_type, &els_type),
+            };
+            let thn = coerce(thn, thn_source, &thn_type, &t);
+            let els = coerce(els, els_source, &els_type, &t);
+            let (tst, thn, els) = (Rc::new(tst), Rc::new(thn), Rc::new(els));
+            Ok((t, Exp::Cnd { tst, thn, els }))
         }
 
         Exp::Let1 { var, value, body } => {
-            let val_type = tc(value, tnv)?;
+            let (val_type, value) = synth(value, tnv, effects)?;
             let mut new_tnv = tnv.clone();
             new_tnv.insert(var.clone(), val_type);
-            tc(body, &new_tnv)
+            let (t, body) = infer(body, expected, &new_tnv, effects)?;
+            let (value, body) = (Rc::new(value), Rc::new(body));
+            Ok((t, Exp::Let1 { var: var.clone(), value, body }))
         }
 
-        Exp::Lam { var, var_type, body } => {
+        Exp::Lam { var, var_type, body: body_source } => {
+            // the parameter's type, and the type the body is wanted as
+            let (param, result) = match (var_type, expected.map(Type::resolve))
+            {
+                (_, Some(Type::Fun { param, result, .. })) => (
+                    var_type.clone().unwrap_or_else(|| (**param).clone()),
+                    Some(&**result),
+                ),
+                (_, Some(Type::Dyn)) => {
+                    (var_type.clone().unwrap_or(Type::Dyn), Some(&Type::Dyn))
+                }
+                (Some(var_type), _) => (var_type.clone(), None),
+                (None, Some(_)) => {
+                    return Err(format!(
+                        "checking {} against {}: only a function type can give {var} a type",
+                        pretty::flat(e),
+                        expected.unwrap()
+                    ));
+                }
+                (None, None) => {
+                    return Err(format!(
+                        "cannot synthesize a type for {}: its parameter {var} has no annotation",
+                        pretty::flat(e)
+                    ));
+                }
+            };
             let mut new_tnv = tnv.clone();
-            new_tnv.insert(var.clone(), var_type.clone());
-            let body_type = tc(body, &new_tnv)?;
-            Ok(Type::Fun {
-                param: Box::new(var_type.clone()),
+            new_tnv.insert(var.clone(), param.clone());
+            // the body's effects happen when the function is called
+            let mut body_effects = EffectRow::new();
+            let (body_type, body) =
+                infer(body_source, result, &new_tnv, &mut body_effects)?;
+            if let Some(result) = result
+                && var_type.is_none()
+                && !consistent(&body_type, result)
+            {
+                return Err(format!(
+                    "checking {} against {}: its body has type {body_type}, not {result}",
+                    pretty::flat(e),
+                    expected.unwrap()
+                ));
+            }
+            let t = Type::Fun {
+                param: Box::new(param.clone()),
                 result: Box::new(body_type),
-            })
+                effects: body_effects,
+            };
+            let (var, var_type) = (var.clone(), Some(param));
+            Ok((t, Exp::Lam { var, var_type, body: Rc::new(body) }))
         }
 
-        Exp::App { fun, arg } => {
-            let fun_type = tc(fun, tnv)?;
-            let arg_type = tc(arg, tnv)?;
-            match fun_type {
-                Type::Fun { param, result } => {
-                    if *param == arg_type {
-                        Ok(*result)
+        Exp::App { fun: fun_source, arg: arg_source } => {
+            let (fun_type, fun) = synth(fun_source, tnv, effects)?;
+            // the argument is checked against the parameter type
+            let (arg_type, arg) = match fun_type.resolve() {
+                Type::Fun { param, .. } | Type::Cont { param } => {
+                    check(arg_source, param, tnv, effects)?
+                }
+                _ => synth(arg_source, tnv, effects)?,
+            };
+            let app =
+                |fun, arg| Exp::App { fun: Rc::new(fun), arg: Rc::new(arg) };
+            // look through an alias to the function type it names
+            match fun_type.resolve() {
+                Type::Fun { param, result, effects: called } => {
+                    let mut bound = HashMap::new();
+                    bind_effects(param, &arg_type, &mut bound);
+                    let expected = subst_effects(param, &bound);
+                    if consistent(&arg_type, &expected) {
+                        effects.extend(subst_row(called, &bound));
+                        let arg = coerce(arg, arg_source, &arg_type, &expected);
+                        Ok((subst_effects(result, &bound), app(fun, arg)))
+                    } else {
+                        Err(mismatch(
+                            format!(
+                                "function argument type mismatch: expected {param}, got {arg_type}"
+                            ),
+                            &arg_type,
+                            &expected,
+                            true,
+                        ))
+                    }
+                }
+                // a continuation never returns, so a throw fits wherever a
+                // value is expected
+                Type::Cont { param } => {
+                    if consistent(&arg_type, param) {
+                        let arg = coerce(arg, arg_source, &arg_type, param);
+                        Ok((Type::Never, app(fun, arg)))
                     } else {
-                        Err(format!(
-                            "function argument type mismatch: expected {param}, got {arg_type}"
+                        Err(mismatch(
+                            format!(
+                                "continuation argument type mismatch: expected {param}, got {arg_type}"
+                            ),
+                            &arg_type,
+                            param,
+                            true,
                         ))
                     }
                 }
-                _ => Err(format!("function expected, found {fun_type}")),
+                // a dyn is checked to be a function when it is called
+                Type::Dyn => {
+                    let fun_type = Type::Fun {
+                        param: Box::new(arg_type),
+                        result: Box::new(Type::Dyn),
+                        effects: EffectRow::new(),
+                    };
+                    let fun = coerce(fun, fun_source, &Type::Dyn, &fun_type);
+                    Ok((Type::Dyn, app(fun, arg)))
+                }
+                // the function never arrives, so neither does a result
+                Type::Never => Ok((Type::Never, app(fun, arg))),
+                _ => Err(too_many_arguments(fun_source, tnv).unwrap_or_else(
+                    || format!("function expected, found {fun_type}"),
+                )),
             }
         }
+
+        // the first part is run for its effect, whatever its type
+        Exp::Seq { first, second } => {
+            let (_, first) = synth(first, tnv, effects)?;
+            let (t, second) = infer(second, expected, tnv, effects)?;
+            let (first, second) = (Rc::new(first), Rc::new(second));
+            Ok((t, Exp::Seq { first, second }))
+        }
+
+        // print takes a value of any type
+        Exp::Print { arg } => {
+            let (_, arg) = synth(arg, tnv, effects)?;
+            effects.insert(IO.to_string());
+            Ok((Type::Unit, Exp::Print { arg: Rc::new(arg) }))
+        }
+
+        Exp::Ascribe { exp: source, typ } => {
+            let (exp_type, exp) = check(source, typ, tnv, effects)?;
+            // the ascribed type, so that an alias named there is kept
+            if consistent(&exp_type, typ) {
+                let exp = Rc::new(coerce(exp, source, &exp_type, typ));
+                Ok((typ.clone(), Exp::Ascribe { exp, typ: typ.clone() }))
+            } else {
+                Err(mismatch(
+                    format!(
+                        "type ascription mismatch: expected {typ}, got {exp_type}"
+                    ),
+                    &exp_type,
+                    typ,
+                    true,
+                ))
+            }
+        }
+
+        // the letcc's value is either its body's or one thrown to var
+        Exp::LetCC { var, var_type, body } => {
+            let Type::Cont { param } = var_type.resolve() else {
+                return Err(format!(
+                    "letcc expects a continuation type, got {var_type}"
+                ));
+            };
+            let mut new_tnv = tnv.clone();
+            new_tnv.insert(var.clone(), var_type.clone());
+            let (body_type, body) = check(body, param, &new_tnv, effects)?;
+            if fits(&body_type, param) {
+                let (var, var_type) = (var.clone(), var_type.clone());
+                let body = Rc::new(body);
+                Ok(((**param).clone(), Exp::LetCC { var, var_type, body }))
+            } else {
+                Err(mismatch(
+                    format!(
+                        "letcc body type mismatch: expected {param}, got {body_type}"
+                    ),
+                    &body_type,
+                    param,
+                    false,
+                ))
+            }
+        }
+
+        Exp::Raise { arg } => {
+            let (arg_type, arg) = synth(arg, tnv, effects)?;
+            if fits(&arg_type, &Type::Str) {
+                Ok((Type::Never, Exp::Raise { arg: Rc::new(arg) }))
+            } else {
+                Err(format!("raise expects a str, got {arg_type}"))
+            }
+        }
+
+        // the exception is always a str, the message of a runtime error
+        // or the argument of a raise
+        Exp::Try { body: body_source, var, handler: handler_source } => {
+            let (body_type, body) = infer(body_source, expected, tnv, effects)?;
+            let mut new_tnv = tnv.clone();
+            new_tnv.insert(var.clone(), Type::Str);
+            let (handler_type, handler) =
+                infer(handler_source, expected, &new_tnv, effects)?;
+            // as for a conditional's branches
+            let wanted = expected
+                .filter(|t| fits(&body_type, t) && fits(&handler_type, t));
+            let t = match (join(&body_type, &handler_type), wanted) {
+                (Some(t), _) => t,
+                (None, Some(t)) => t.clone(),
+                (None, None) => meet(&body_type, &handler_type),
+            };
+            let body = coerce(body, body_source, &body_type, &t);
+            let handler = coerce(handler, handler_source, &handler_type, &t);
+            let (body, handler) = (Rc::new(body), Rc::new(handler));
+            Ok((t, Exp::Try { body, var: var.clone(), handler }))
+        }
+
+        Exp::Perform { op, arg, param, result } => {
+            let (arg_type, arg) = check(arg, param, tnv, effects)?;
+            if !fits(&arg_type, param) {
+                return Err(mismatch(
+                    format!("{op} expects {param}, got {arg_type}"),
+                    &arg_type,
+                    param,
+                    false,
+                ));
+            }
+            effects.insert(op.clone());
+            let e = Exp::Perform {
+                op: op.clone(),
+                arg: Rc::new(arg),
+                param: param.clone(),
+                result: result.clone(),
+            };
+            Ok((*result.clone(), e))
+        }
+
+        Exp::Handle { body, clauses, ret } => {
+            let mut body_effects = EffectRow::new();
+            let (body_type, body) = synth(body, tnv, &mut body_effects)?;
+            for clause in clauses {
+                body_effects.remove(&clause.op);
+            }
+            let (returned, ret) = match ret {
+                Some(Return { var, body: ret_body }) => {
+                    let mut new_tnv = tnv.clone();
+                    new_tnv.insert(var.clone(), body_type);
+                    let (t, ret_body) =
+                        synth(ret_body, &new_tnv, &mut body_effects)?;
+                    let body = Rc::new(ret_body);
+                    (t, Some(Return { var: var.clone(), body }))
+                }
+                None => (body_type, None),
+            };
+            // a body that never returns, such as a throw, leaves the
+            // clauses to decide the type
+            let open = returned.resolve() == &Type::Never;
+            let mut handle_type = returned.clone();
+            // resuming runs the rest of the body and the return clause,
+            // with whatever effects they leave unhandled
+            let mut checked = Vec::new();
+            for clause in clauses {
+                let Clause { op, param, result, var, k, body } = clause;
+                let k_type = Type::Fun {
+                    param: Box::new(result.clone()),
+                    result: Box::new(returned.clone()),
+                    effects: body_effects.clone(),
+                };
+                let mut new_tnv = tnv.clone();
+                new_tnv.insert(var.clone(), param.clone());
+                new_tnv.insert(k.clone(), k_type);
+                let wanted = if open { expected } else { Some(&returned) };
+                let (clause_type, body) =
+                    infer(body, wanted, &new_tnv, effects)?;
+                match join(&handle_type, &clause_type) {
+                    Some(t) if open => handle_type = t,
+                    _ if fits(&clause_type, &handle_type) => {}
+                    _ => {
+                        return Err(mismatch(
+                            format!(
+                                "{op} clause type mismatch: expected {handle_type}, got {clause_type}"
+                            ),
+                            &clause_type,
+                            &handle_type,
+                            false,
+                        ));
+                    }
+                }
+                checked.push(Clause {
+                    op: op.clone(),
+                    param: param.clone(),
+                    result: result.clone(),
+                    var: var.clone(),
+                    k: k.clone(),
+                    body: Rc::new(body),
+                });
+            }
+            effects.extend(body_effects);
+            let body = Rc::new(body);
+            Ok((handle_type, Exp::Handle { body, clauses: checked, ret }))
+        }
+
+        // a cast the checker inserted, checked again
+        Exp::Cast { exp, from, to, label } => {
+            let (exp_type, exp) = synth(exp, tnv, effects)?;
+            if !consistent(&exp_type, from) {
+                return Err(format!(
+                    "cast from {from} applied to a {exp_type}"
+                ));
+            }
+            let e = Exp::Cast {
+                exp: Rc::new(exp),
+                from: from.clone(),
+                to: to.clone(),
+                label: label.clone(),
+            };
+            Ok(((**to).clone(), e))
+        }
+
+        // the elaboration has no use for positions once casts are labelled
+        Exp::At { exp, .. } => infer(exp, expected, tnv, effects),
+    }
+}
+
+// The error for calling `fun`, which gives no function, if it is itself
+// a call to a function given more arguments than it takes, as in
+// add(1, 2, 3).
+fn too_many_arguments(fun: &Exp, tnv: &TEnv) -> Option<String> {
+    let (mut head, mut args) = (fun.bare(), 1);
+    while let Exp::App { fun, .. } = head {
+        head = fun.bare();
+        args += 1;
+    }
+    let head_type = tc(head, tnv).ok()?;
+    let (mut t, mut params) = (&head_type, 0);
+    while let Type::Fun { result, .. } = t {
+        t = result;
+        params += 1;
+    }
+    if args == 1 || params >= args {
+        return None;
+    }
+    let plural = if params == 1 { "" } else { "s" };
+    Some(format!("function expects {params} argument{plural}, got {args}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_expression, parse_expression_with, tokenize};
+
+    fn parse(input: &str) -> Exp {
+        parse_expression(&tokenize(input).unwrap()).unwrap()
+    }
+
+    fn eval(input: &str) -> Result<Value, String> {
+        interp(&parse(input), &Env::new())
+    }
+
+    fn check(input: &str) -> Result<Type, String> {
+        tc(&parse(input), &TEnv::new())
+    }
+
+    fn fun(param: Type, result: Type) -> Type {
+        Type::Fun {
+            param: Box::new(param),
+            result: Box::new(result),
+            effects: EffectRow::new(),
+        }
+    }
+
+    #[test]
+    fn interp_literals() {
+        assert_eq!(eval("5"), Ok(Value::Int(5)));
+        assert_eq!(eval("true"), Ok(Value::Bool(true)));
+        assert_eq!(eval("\"s\""), Ok(Value::Str("s".to_string())));
+    }
+
+    #[test]
+    fn interp_variables() {
+        let mut nv = Env::new();
+        nv.insert("x".to_string(), Value::Int(3));
+        assert_eq!(interp(&parse("x"), &nv), Ok(Value::Int(3)));
+        assert_eq!(eval("x"), Err("x not bound".to_string()));
+    }
+
+    #[test]
+    fn interp_primitives() {
+        assert_eq!(eval("1 + -2 + 3"), Ok(Value::Int(2)));
+        assert_eq!(eval("\"a\" ++ \"b\""), Ok(Value::Str("ab".to_string())));
+        assert_eq!(eval("1 < 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval("2 < 2"), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn interp_primitive_errors() {
+        assert_eq!(
+            eval("1 + true"),
+            Err(
+                "+ expects two integers or two floats, got Int(1) + Bool(true)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            eval("9223372036854775807 + 1"),
+            Err("integer overflow in 9223372036854775807 + 1".to_string())
+        );
+        assert_eq!(
+            eval("-9223372036854775808 + -1"),
+            Err("integer overflow in -9223372036854775808 + -1".to_string())
+        );
+        assert_eq!(
+            eval("9223372036854775807 + -1 + 1"),
+            Ok(Value::Int(isize::MAX))
+        );
+        assert_eq!(
+            eval("1 ++ \"a\""),
+            Err("++ expects two strings, got Int(1) ++ Str(\"a\")".to_string())
+        );
+        assert_eq!(
+            eval("\"a\" < 1"),
+            Err(
+                "< expects two integers or two floats, got Str(\"a\") < Int(1)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn interp_string_primitives() {
+        let str = |s: &str| Ok(Value::Str(s.to_string()));
+        assert_eq!(eval("length(\"a\\tb\")"), Ok(Value::Int(3)));
+        assert_eq!(eval("substring(\"hello\")(1)(3)"), str("el"));
+        assert_eq!(eval("str-eq(\"a\")(\"a\")"), Ok(Value::Bool(true)));
+        assert_eq!(eval("int->str(1 + 2) ++ \"!\""), str("3!"));
+        assert_eq!(eval("int-or(str->int(\"-4\"))(0) + 1"), Ok(Value::Int(-3)));
+        // a string that isn't an integer is told apart without an error
+        let parse = "let n = str->int(\"four\") {\n\
+                     if is-int(n) { int->str(int-or(n)(0)) } else { \"nan\" } }";
+        assert_eq!(eval(parse), str("nan"));
+        // a binding hides the primitive of the same name
+        assert_eq!(eval("let length = 1 { length }"), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn interp_floats() {
+        assert_eq!(eval("1.5 + -2e3"), Ok(Value::Float(-1998.5)));
+        assert_eq!(eval("0.5 < 1.0"), Ok(Value::Bool(true)));
+        assert_eq!(eval("int->float(3)"), Ok(Value::Float(3.0)));
+        assert_eq!(eval("float->int(-2.75)"), Ok(Value::Int(-2)));
+        assert_eq!(
+            eval("float->int(1e300)"),
+            Err("float->int: 1e300 is out of range".to_string())
+        );
+        assert_eq!(
+            eval("1 + 1.0"),
+            Err(
+                "+ expects two integers or two floats, got Int(1) + Float(1.0)"
+                    .to_string()
+            )
+        );
+        // whole floats keep their fraction, so they don't print as ints
+        assert_eq!(eval("1.0 + 1.0").unwrap().to_string(), "2.0");
+        assert_eq!(eval("1e308 + 1e308").unwrap().to_string(), "inf");
+    }
+
+    #[test]
+    fn interp_sequences_and_print() {
+        assert_eq!(
+            with_output(|| eval("print(\"a\tb\"); print(1 + 1); 3")),
+            (Ok(Value::Int(3)), "a\tb\n2\n".to_string())
+        );
+        assert_eq!(
+            with_output(|| eval("print(print(1.5))")),
+            (Ok(Value::Unit), "1.5\n()\n".to_string())
+        );
+        // output before an error is still written
+        assert_eq!(
+            with_output(|| eval("print(1); substring(\"x\", 0, 2)")),
+            (
+                Err("substring 0 2 out of range for a string of length 1"
+                    .to_string()),
+                "1\n".to_string()
+            )
+        );
+        // captures nest, each seeing only its own output
+        let (inner, outer) = with_output(|| {
+            eval("print(1)").unwrap();
+            with_output(|| eval("print(2)")).1
+        });
+        assert_eq!((inner, outer), ("2\n".to_string(), "1\n".to_string()));
+        assert_eq!(eval("fn(u: unit) { 7 }()"), Ok(Value::Int(7)));
+        assert_eq!(eval("fn() { 7 }()"), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn interp_lazy_strategies() {
+        let run = |input: &str, strategy| {
+            with_output(|| interp_with(&parse(input), &Env::new(), strategy))
+        };
+        let unused = "let x = (print(\"x\"); substring(\"x\", 0, 2)) { 1 }";
+        assert_eq!(
+            run(unused, Strategy::ByValue),
+            (
+                Err("substring 0 2 out of range for a string of length 1"
+                    .to_string()),
+                "x\n".to_string()
+            )
+        );
+        assert_eq!(
+            run(unused, Strategy::ByName),
+            (Ok(Value::Int(1)), String::new())
+        );
+        assert_eq!(
+            run(unused, Strategy::ByNeed),
+            (Ok(Value::Int(1)), String::new())
+        );
+
+        // by name runs the argument at each use, by need only at the first,
+        // even when it is passed on through other variables
+        let used = "let twice = fn(x: int) { x + x } {\n\
+                    fn(y: int) { twice(y) + y }((print(\"y\"); 1)) }";
+        assert_eq!(
+            run(used, Strategy::ByValue),
+            (Ok(Value::Int(3)), "y\n".to_string())
+        );
+        assert_eq!(
+            run(used, Strategy::ByName),
+            (Ok(Value::Int(3)), "y\ny\ny\n".to_string())
+        );
+        assert_eq!(
+            run(used, Strategy::ByNeed),
+            (Ok(Value::Int(3)), "y\n".to_string())
+        );
+
+        // primitives, operators and conditionals force their operands
+        let forced = "fn(s: str) { if length(s) < 3 { s ++ \"!\" } else { s } }(\"a\" ++ \"b\")";
+        for strategy in [Strategy::ByName, Strategy::ByNeed] {
+            assert_eq!(
+                run(forced, strategy).0,
+                Ok(Value::Str("ab!".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn interp_exceptions() {
+        assert_eq!(
+            eval("try { 1 + raise \"no\" } catch (e) { 2 }"),
+            Ok(Value::Int(2))
+        );
+        // the exception unwinds through lets and calls to the nearest try
+        assert_eq!(
+            eval(
+                "let f = fn(x: int) { let y = raise \"deep\" { y } } {\n\
+                 try { try { f(1) } catch (e) { raise (e ++ \"er\") } } catch (e) { e } }"
+            ),
+            Ok(Value::Str("deeper".to_string()))
+        );
+        // runtime errors are caught with their message
+        assert_eq!(
+            eval("try { 9223372036854775807 + 1 } catch (e) { e }"),
+            Ok(Value::Str(
+                "integer overflow in 9223372036854775807 + 1".to_string()
+            ))
+        );
+        assert_eq!(
+            eval("try { substring(\"abc\", 2, 9) } catch (e) { \"-\" }"),
+            Ok(Value::Str("-".to_string()))
+        );
+        assert_eq!(eval("raise \"up\""), Err("up".to_string()));
+        assert_eq!(eval("try { 1 } catch (e) { raise e }"), Ok(Value::Int(1)));
+        // the body's output is kept when it raises
+        assert_eq!(
+            with_output(|| eval(
+                "try { print(1); raise \"x\" } catch (e) { 2 }"
+            )),
+            (Ok(Value::Int(2)), "1\n".to_string())
+        );
+    }
+
+    #[test]
+    fn interp_tail_calls_run_in_constant_stack() {
+        // there is no recursion yet, but a function taking a dyn can be
+        // handed itself. The recursive call is in tail position in a
+        // closure body: in a conditional branch, a let body, the last part
+        // of a sequence and an ascription.
+        let count = "let count = fn(me: dyn) { fn(n: int) {\n\
+                     if n < 1 { (n : dyn) } else {\n\
+                     let next = n + -1 { (); (me(me)(next) : dyn) } } } } {\n\
+                     count(count)(1000000) }";
+        let (e, t, _) = elaborate(&parse(count), &TEnv::new()).unwrap();
+        assert_eq!(t, Type::Dyn);
+        assert_eq!(interp(&e, &Env::new()), Ok(Value::Int(0)));
+    }
+
+    #[test]
+    fn interp_stops_unbounded_recursion_through_dyn() {
+        // the recursive call is not in tail position, so each level nests
+        // deeper; run on a stack the size the REPL is given
+        let deep = "let f = (fn(me: dyn) { fn(n: int) {\n\
+                    if n < 1 { 0 } else { 1 + (me(me)(n + -1) : int) } } } \
+                    : dyn) { f(f)(100000) }";
+        let run = move || {
+            let (e, _, _) = elaborate(&parse(deep), &TEnv::new())?;
+            interp(&e, &Env::new()).map(|v| v.to_string())
+        };
+        let outcome = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(
+            outcome,
+            Err(format!("evaluation nested more than {MAX_DEPTH} levels deep"))
+        );
+        // the limit applies to nesting, not to the total work done
+        assert_eq!(
+            eval("let f = fn(n: int) { n + 1 } { f(f(f(1))) + f(1) }"),
+            Ok(Value::Int(6))
+        );
+    }
+
+    #[test]
+    fn interp_conditional() {
+        assert_eq!(eval("if true { 1 } else { 2 }"), Ok(Value::Int(1)));
+        assert_eq!(eval("if false { 1 } else { 2 }"), Ok(Value::Int(2)));
+        // only the selected branch is evaluated
+        assert_eq!(eval("if true { 1 } else { 1 + true }"), Ok(Value::Int(1)));
+        assert_eq!(
+            eval("if 0 { 1 } else { 2 }"),
+            Err("boolean expected, found Int(0)".to_string())
+        );
+    }
+
+    #[test]
+    fn interp_let1_shadows() {
+        assert_eq!(eval("let x = 1 { x + x }"), Ok(Value::Int(2)));
+        assert_eq!(
+            eval("let z = 123 { let z = 4 { z + 2 } + z }"),
+            Ok(Value::Int(129))
+        );
+        // the bound expression sees the outer binding
+        assert_eq!(
+            eval("let x = 1 { let x = x + 1 { x } }"),
+            Ok(Value::Int(2))
+        );
+    }
+
+    #[test]
+    fn interp_lambda_builds_closure() {
+        let mut nv = Env::new();
+        nv.insert("x".to_string(), Value::Int(1));
+        assert_eq!(
+            interp(&parse("fn(y: int) { x }"), &nv),
+            Ok(Value::Fun {
+                var: "y".to_string(),
+                var_type: Type::Int,
+                body: Rc::new(Exp::Var("x".to_string())),
+                nv,
+            })
+        );
+    }
+
+    #[test]
+    fn interp_application() {
+        assert_eq!(eval("fn(a: int) { a + a }(13)"), Ok(Value::Int(26)));
+        assert_eq!(
+            eval(
+                "let adder = fn(x: int) { fn(y: int) { x + y } } { adder(2)(3) }"
+            ),
+            Ok(Value::Int(5))
+        );
+        assert_eq!(
+            eval("1(2)"),
+            Err("function expected, found Int(1)".to_string())
+        );
+    }
+
+    #[test]
+    fn interp_closures_are_statically_scoped() {
+        assert_eq!(
+            eval("let x = 1 { let f = fn(y:int){x} { let x = 2 { f(10) } } }"),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            eval("let f = fn(y:int) {x} { let x = 1 { f(10) } }"),
+            Err("x not bound".to_string())
+        );
+        // the parameter shadows a captured variable of the same name
+        assert_eq!(
+            eval("let y = 3 { fn(y:int) { y + 1 } }(5)"),
+            Ok(Value::Int(6))
+        );
+    }
+
+    #[test]
+    fn tc_literals_and_variables() {
+        assert_eq!(check("1"), Ok(Type::Int));
+        assert_eq!(check("false"), Ok(Type::Bool));
+        assert_eq!(check("\"s\""), Ok(Type::Str));
+        assert_eq!(check("x"), Err("no known type for x".to_string()));
+        let mut tnv = TEnv::new();
+        tnv.insert("x".to_string(), Type::Bool);
+        assert_eq!(tc(&parse("x"), &tnv), Ok(Type::Bool));
+    }
+
+    #[test]
+    fn tc_primitives() {
+        assert_eq!(check("1 + 2"), Ok(Type::Int));
+        assert_eq!(check("1 + true"), Err("not both integers".to_string()));
+        assert_eq!(check("\"a\" ++ \"b\""), Ok(Type::Str));
+        assert_eq!(check("\"a\" ++ 1"), Err("not both strings".to_string()));
+        assert_eq!(check("1 < 2"), Ok(Type::Bool));
+        assert_eq!(check("\"a\" < \"b\""), Err("not both numbers".to_string()));
+    }
+
+    #[test]
+    fn tc_string_primitives() {
+        assert_eq!(check("length"), Ok(fun(Type::Str, Type::Int)));
+        assert_eq!(
+            check("substring(\"abc\")(0)"),
+            Ok(fun(Type::Int, Type::Str))
+        );
+        assert_eq!(check("str-eq(\"a\")(\"b\")"), Ok(Type::Bool));
+        assert_eq!(check("str->int(int->str(1))"), Ok(Type::IntOption));
+        assert_eq!(check("is-int(str->int(\"1\"))"), Ok(Type::Bool));
+        assert_eq!(check("int-or(str->int(\"1\"))(0) + 1"), Ok(Type::Int));
+        assert_eq!(
+            check("str->int(\"1\") + 1"),
+            Err("not both integers".to_string())
+        );
+        assert_eq!(
+            check("length(1)"),
+            Err("function argument type mismatch: expected str, got int"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn tc_floats() {
+        assert_eq!(check("1.5 + 2.0"), Ok(Type::Float));
+        assert_eq!(check("1.5 < 2.0"), Ok(Type::Bool));
+        assert_eq!(check("1.5 + 2"), Err("not both floats".to_string()));
+        assert_eq!(check("1 + 2.0"), Err("not both integers".to_string()));
+        assert_eq!(check("1 < 2.0"), Err("not both numbers".to_string()));
+        assert_eq!(check("int->float"), Ok(fun(Type::Int, Type::Float)));
+        assert_eq!(
+            check("fn(x: float) { float->int(x) }"),
+            Ok(fun(Type::Float, Type::Int))
+        );
+    }
+
+    #[test]
+    fn tc_several_arguments() {
+        let add = "fn(x: int, y: int) { x + y }";
+        assert_eq!(check(add), Ok(fun(Type::Int, fun(Type::Int, Type::Int))));
+        assert_eq!(check(&format!("{add}(1, 2)")), Ok(Type::Int));
+        // too few arguments leaves a function of the rest
+        assert_eq!(check(&format!("{add}(1)")), Ok(fun(Type::Int, Type::Int)));
+        assert_eq!(
+            check(&format!("{add}(1, 2, 3)")),
+            Err("function expects 2 arguments, got 3".to_string())
+        );
+        assert_eq!(
+            check("fn(x: int) { x }(1, 2)"),
+            Err("function expects 1 argument, got 2".to_string())
+        );
+        assert_eq!(
+            check(&format!("{add}(1, \"2\")")),
+            Err("function argument type mismatch: expected int, got str"
+                .to_string())
+        );
+        assert_eq!(eval(&format!("{add}(1, 2)")), Ok(Value::Int(3)));
+        assert_eq!(
+            eval("substring(\"hello\", 1, 3)"),
+            Ok(Value::Str("el".to_string()))
+        );
+    }
+
+    #[test]
+    fn tc_unit_sequences_and_print() {
+        assert_eq!(check("()"), Ok(Type::Unit));
+        assert_eq!(check("print(fn(x: int) { x })"), Ok(Type::Unit));
+        assert_eq!(check("1; \"s\""), Ok(Type::Str));
+        assert_eq!(
+            check("print(1 + true); 1"),
+            Err("not both integers".to_string())
+        );
+        assert_eq!(check("fn(u: unit) { 1 }"), Ok(fun(Type::Unit, Type::Int)));
+        assert_eq!(check("fn() { 1 }"), Ok(fun(Type::Unit, Type::Int)));
+        assert_eq!(
+            check("fn(u: unit) { 1 }(2)"),
+            Err("function argument type mismatch: expected unit, got int"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn tc_ascriptions() {
+        assert_eq!(check("(1 + 2 : int)"), Ok(Type::Int));
+        assert_eq!(
+            check("(fn(x: int) { x } : (int -> int))(1)"),
+            Ok(Type::Int)
+        );
+        assert_eq!(
+            check("(1 : str)"),
+            Err("type ascription mismatch: expected str, got int".to_string())
+        );
+        assert_eq!(check("let s: str = \"a\" { s ++ s }"), Ok(Type::Str));
+        assert_eq!(
+            check("let f: (int -> bool) = fn(x: int) { x } { f }"),
+            Err("type ascription mismatch: expected (int -> bool), \
+                 got (int -> int): in the result, int is not a subtype of bool"
+                .to_string())
+        );
+        // ascriptions are erased at run time
+        assert_eq!(eval("let x: int = 4 { (x + 1 : int) }"), Ok(Value::Int(5)));
+    }
+
+    #[test]
+    fn tc_type_aliases() {
+        let mut aliases = Aliases::new();
+        aliases.insert("Num".to_string(), Type::Int);
+        let fun_type = fun(Type::Int, Type::Int);
+        aliases.insert("IntFn".to_string(), fun_type);
+        let check = |input: &str| {
+            let tokens = tokenize(input).unwrap();
+            tc(
+                &parse_expression_with(&tokens, &aliases, &Ops::new()).unwrap(),
+                &TEnv::new(),
+            )
+        };
+        assert_eq!(
+            check("fn(x: Num) { x + 1 }").unwrap().to_string(),
+            "(Num -> int)"
+        );
+        assert_eq!(
+            check("fn(f: IntFn) { f(1) < 2 }(fn(x: int) { x })"),
+            Ok(Type::Bool)
+        );
+        assert_eq!(check("(fn(x: int) { x } : IntFn)(1)"), Ok(Type::Int));
+        // errors name the alias rather than what it stands for
+        assert_eq!(
+            check("fn(x: Num) { x }(true)"),
+            Err("function argument type mismatch: expected Num, got bool"
+                .to_string())
+        );
+        assert_eq!(
+            check("fn(f: IntFn) { f }(1)"),
+            Err("function argument type mismatch: expected IntFn, got int"
+                .to_string())
+        );
+        assert_eq!(
+            check("let n: Num = 1 { n(2) }"),
+            Err("function expected, found Num".to_string())
+        );
+    }
+
+    #[test]
+    fn tc_letcc() {
+        assert_eq!(check("letcc k: cont int { 1 + k(2) }"), Ok(Type::Int));
+        // a throw never returns, so it fits wherever a value is expected
+        assert_eq!(
+            check("letcc k: cont str { if true { k(\"a\") } else { \"b\" } }"),
+            Ok(Type::Str)
+        );
+        assert_eq!(
+            check("letcc k: cont int { let s = \"a\" ++ k(1) { 2 } }"),
+            Ok(Type::Int)
+        );
+        assert_eq!(
+            check("letcc k: cont int { if k(1) { 2 } else { 3 } }"),
+            Ok(Type::Int)
+        );
+        assert_eq!(
+            check("fn(k: cont int) { k(1) }").unwrap().to_string(),
+            "(cont int -> never)"
+        );
+        assert_eq!(
+            check("letcc k: cont int { k(()); 1 }"),
+            Err("continuation argument type mismatch: expected int, got unit"
+                .to_string())
+        );
+        assert_eq!(
+            check("letcc k: cont int { \"a\" }"),
+            Err("letcc body type mismatch: expected int, got str".to_string())
+        );
+        assert_eq!(
+            check("letcc k: (int -> int) { 1 }"),
+            Err("letcc expects a continuation type, got (int -> int)"
+                .to_string())
+        );
+        assert_eq!(
+            check("fn(k: cont int) { k(1) + 1 }").unwrap().to_string(),
+            "(cont int -> int)"
+        );
+    }
+
+    #[test]
+    fn tc_exceptions() {
+        assert_eq!(check("raise \"x\""), Ok(Type::Never));
+        // never fits wherever a value is expected
+        assert_eq!(check("if true { raise \"x\" } else { 1 }"), Ok(Type::Int));
+        assert_eq!(check("raise \"x\" ++ \"y\""), Ok(Type::Str));
+        assert_eq!(check("fn(x: int) { x }(raise \"x\")"), Ok(Type::Int));
+        assert_eq!(check("(raise \"x\" : bool)"), Ok(Type::Bool));
+        assert_eq!(
+            check("raise 1"),
+            Err("raise expects a str, got int".to_string())
+        );
+        assert_eq!(check("try { 1 } catch (e) { e ++ \"!\" }"), Ok(Type::Top));
+        assert_eq!(
+            check("try { raise \"x\" } catch (e) { e ++ \"!\" }"),
+            Ok(Type::Str)
+        );
+        // the exception is bound only in the handler
+        assert_eq!(
+            check("try { e } catch (e) { e }"),
+            Err("no known type for e".to_string())
+        );
+    }
+
+    #[test]
+    fn tc_effects_and_handlers() {
+        let ops = Ops::from([
+            ("Get".to_string(), (Type::Unit, Type::Int)),
+            ("Put".to_string(), (Type::Int, Type::Unit)),
+        ]);
+        let check = |input: &str| {
+            let tokens = tokenize(input).unwrap();
+            let e = parse_expression_with(&tokens, &Aliases::new(), &ops);
+            tc_effects(&e.unwrap(), &TEnv::new())
+                .map(|(t, row)| format!("{t} {row:?}"))
+        };
+        assert_eq!(check("perform Get(())"), Ok("int {\"Get\"}".to_string()));
+        assert_eq!(
+            check("perform Put(true)"),
+            Err("Put expects int, got bool".to_string())
+        );
+        // a function's type carries the effects of its body
+        assert_eq!(
+            check("fn(f: (unit -{Get}-> int)) { perform Put(f(())) }"),
+            Ok("((unit -{Get}-> int) -{Get, Put}-> unit) {}".to_string())
+        );
+        // a function with fewer effects fits where more are allowed
+        assert_eq!(
+            check("fn(f: (unit -{Get}-> int)) { 1 }(fn(u: unit) { 2 })"),
+            Ok("int {}".to_string())
+        );
+        assert_eq!(
+            check(
+                "fn(f: (unit -> int)) { 1 }(fn(u: unit) { perform Get(()) })"
+            ),
+            Err("function argument type mismatch: expected (unit -> int), \
+                 got (unit -{Get}-> int): in the effects, Get is not allowed"
+                .to_string())
+        );
+        // a handler removes the operations it has clauses for
+        assert_eq!(
+            check(
+                "handle perform Put(perform Get(())) with { Put(n, k) -> k(()) }"
+            ),
+            Ok("unit {\"Get\"}".to_string())
+        );
+        assert_eq!(
+            check(
+                "handle perform Get(()) with { Get(u, k) -> k(1); \
+                   return x -> x < 3 }"
+            ),
+            Ok("bool {}".to_string())
+        );
+        assert_eq!(
+            check("handle perform Get(()) with { Get(u, k) -> \"x\" }"),
+            Err("Get clause type mismatch: expected int, got str".to_string())
+        );
+        // a body that never returns takes its type from the clauses
+        assert_eq!(
+            check(
+                "handle (perform Get(()); raise \"x\") with { Get(u, k) -> \"y\" }"
+            ),
+            Ok("str {}".to_string())
+        );
+    }
+
+    #[test]
+    fn tc_io_and_effect_polymorphism() {
+        let check = |input: &str| {
+            tc_effects(&parse(input), &TEnv::new())
+                .map(|(t, row)| format!("{t} {row:?}"))
+        };
+        assert_eq!(check("print(1)"), Ok("unit {\"io\"}".to_string()));
+        assert_eq!(
+            check("fn(x: int) { print(x); x }"),
+            Ok("(int -{io}-> int) {}".to_string())
+        );
+        assert_eq!(
+            check("fn(x: int) { fn(y: int) { print(y); x } }(1)"),
+            Ok("(int -{io}-> int) {}".to_string())
+        );
+        // a pure function is one whose type has no effects
+        assert_eq!(
+            check("fn(f: (int -> int)) { f(1) }(fn(x: int) { print(x); x })"),
+            Err("function argument type mismatch: expected (int -> int), \
+                 got (int -{io}-> int): in the effects, io is not allowed"
+                .to_string())
+        );
+        // a function taking a function has whatever effects it is passed
+        let apply = "fn(f: (int -{e}-> int)) { f(1) }";
+        assert_eq!(
+            check(apply),
+            Ok("((int -{e}-> int) -{e}-> int) {}".to_string())
+        );
+        assert_eq!(
+            check(&format!("{apply}(fn(x: int) {{ x }})")),
+            Ok("int {}".to_string())
+        );
+        assert_eq!(
+            check(&format!("{apply}(fn(x: int) {{ print(x); x }})")),
+            Ok("int {\"io\"}".to_string())
+        );
+        let twice =
+            "let twice = fn(f: (int -{e}-> int)) { fn(x: int) { f(f(x)) } }";
+        assert_eq!(
+            check(&format!("{twice} {{ twice(fn(x: int) {{ x + 1 }}) }}")),
+            Ok("(int -> int) {}".to_string())
+        );
+        assert_eq!(
+            check(&format!(
+                "{twice} {{ twice(fn(x: int) {{ print(x); x }}) }}"
+            )),
+            Ok("(int -{io}-> int) {}".to_string())
+        );
+        // inside, the variable stands for effects the function can't know
+        assert_eq!(
+            check("fn(f: (int -{e}-> int)) { fn(g: (int -> int)) { g }(f) }"),
+            Err("function argument type mismatch: expected (int -> int), \
+                 got (int -{e}-> int): in the effects, e is not allowed"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn tc_dyn_is_consistent_with_every_type() {
+        assert_eq!(check("fn(x: dyn) { x }(1)"), Ok(Type::Dyn));
+        assert_eq!(check("fn(x: int) { x }((true : dyn))"), Ok(Type::Int));
+        assert_eq!(
+            check("fn(f: dyn) { f(1) }").unwrap().to_string(),
+            "(dyn -> dyn)"
+        );
+        assert_eq!(
+            check("fn(f: (dyn -> int)) { f(1) }(fn(x: int) { x })"),
+            Ok(Type::Int)
+        );
+        // branches meet at what they agree on
+        assert_eq!(check("if true { 1 } else { (2 : dyn) }"), Ok(Type::Int));
+        // and operands meet at the type the operator wants
+        assert_eq!(
+            check("fn(x: dyn) { x + 1 }"),
+            Ok(fun(Type::Dyn, Type::Int))
+        );
+        assert_eq!(check("(1 : dyn) + 2"), Ok(Type::Int));
+        assert_eq!(check("1.5 + (2.5 : dyn)"), Ok(Type::Float));
+        assert_eq!(check("(1 : dyn) + (2 : dyn)"), Ok(Type::Int));
+        assert_eq!(check("(\"a\" : dyn) ++ \"b\""), Ok(Type::Str));
+        assert_eq!(check("(1.5 : dyn) < 2.5"), Ok(Type::Bool));
+        assert_eq!(
+            check("(1 : top) + 1"),
+            Err("not both integers".to_string())
+        );
+        assert_eq!(
+            check("1 ++ (\"b\" : dyn)"),
+            Err("not both strings".to_string())
+        );
+        assert_eq!(
+            check("fn(x: int) { x }(true)"),
+            Err("function argument type mismatch: expected int, got bool"
+                .to_string())
+        );
+        assert_eq!(
+            check("if true { (1 : dyn) } else { \"a\" }"),
+            Ok(Type::Str)
+        );
+    }
+
+    #[test]
+    fn casts_blame_the_expression_they_check() {
+        let run = |input: &str| {
+            let (e, _, _) = elaborate(&parse(input), &TEnv::new())?;
+            interp(&e, &Env::new())
+        };
+        assert_eq!(run("fn(x: int) { x + 1 }((41 : dyn))"), Ok(Value::Int(42)));
+        assert_eq!(
+            run("fn(x: int) { x + 1 }((\"2\" : dyn))"),
+            Err("cast from dyn to int failed at (\"2\" : dyn): got str"
+                .to_string())
+        );
+        assert_eq!(
+            run("let f = (fn(x: int) { x + 1 } : dyn) { f(41) }"),
+            Ok(Value::Int(42))
+        );
+        assert_eq!(
+            run("let f = (1 : dyn) { f(41) }"),
+            Err("cast from dyn to (int -> dyn) failed at f: got int"
+                .to_string())
+        );
+        // a function checks its argument once it is called
+        assert_eq!(
+            run("let f = (fn(x: int) { x + 1 } : dyn) { f(true) }"),
+            Err("cast from bool to int failed at f: got bool".to_string())
+        );
+        assert_eq!(
+            run("fn(g: (int -> int)) { g(1) }(fn(x: dyn) { (\"no\" : dyn) })"),
+            Err("cast from dyn to int failed at fn(x: dyn) { (\"no\" : dyn) }: \
+                 got str"
+                .to_string())
+        );
+        assert_eq!(
+            run("if true { (\"a\" : dyn) } else { 1 }"),
+            Err("cast from dyn to int failed at (\"a\" : dyn): got str"
+                .to_string())
+        );
+        // an operand is checked where the operator uses it
+        assert_eq!(run("fn(x: dyn) { x + 1 }(41)"), Ok(Value::Int(42)));
+        assert_eq!(
+            run("fn(x: dyn) { x + 1 }(1.5)"),
+            Err("cast from dyn to int failed at x: got float".to_string())
+        );
+        assert_eq!(
+            run("(\"a\" : dyn) ++ (\"b\" : dyn)"),
+            Ok(Value::Str("ab".to_string()))
+        );
+        assert_eq!(
+            run("1.5 < (2 : dyn)"),
+            Err("cast from dyn to float failed at (2 : dyn): got int"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn tc_checks_unannotated_functions_against_expected_types() {
+        let int_fun = fun(Type::Int, Type::Int);
+        // as the argument of an annotated function
+        assert_eq!(
+            check("fn(f: (int -> int)) { f(1) }(fn(x) { x + 1 })"),
+            Ok(Type::Int)
+        );
+        // as an ascribed let's value, through a conditional's branches
+        assert_eq!(
+            check("let f: (int -> int) = fn(x) { x + 1 } { f }"),
+            Ok(int_fun.clone())
+        );
+        assert_eq!(
+            check(
+                "let f: (int -> (int -> int)) = \
+                 if true { fn(x, y) { x } } else { fn(x, y) { y } } { f }"
+            ),
+            Ok(fun(Type::Int, int_fun.clone()))
+        );
+        // dyn gives an unannotated parameter dyn
+        assert_eq!(check("(fn(x) { x } : dyn)"), Ok(Type::Dyn));
+        // the errors say which mode failed
+        assert_eq!(
+            check("fn(x) { x }"),
+            Err("cannot synthesize a type for fn(x) { x }: \
+                 its parameter x has no annotation"
+                .to_string())
+        );
+        assert_eq!(
+            check("let f = fn(x) { x } { f(1) }"),
+            Err("cannot synthesize a type for fn(x) { x }: \
+                 its parameter x has no annotation"
+                .to_string())
+        );
+        assert_eq!(
+            check("(fn(x) { x } : int)"),
+            Err("checking fn(x) { x } against int: \
+                 only a function type can give x a type"
+                .to_string())
+        );
+        assert_eq!(
+            check("let f: (int -> bool) = fn(x) { x + 1 } { f }"),
+            Err("checking fn(x) { x + 1 } against (int -> bool): \
+                 its body has type int, not bool"
+                .to_string())
+        );
+        let run = |input: &str| {
+            let (e, _, _) = elaborate(&parse(input), &TEnv::new())?;
+            interp(&e, &Env::new())
+        };
+        assert_eq!(
+            run("fn(f: (int -> int)) { f(1) }(fn(x) { x + 1 })"),
+            Ok(Value::Int(2))
+        );
+    }
+
+    #[test]
+    fn tc_subtyping() {
+        // every value is a top, but a top can only be passed on
+        assert_eq!(check("fn(x: top) { 1 }(\"a\")"), Ok(Type::Int));
+        assert_eq!(
+            check("fn(x: top) { x + 1 }"),
+            Err("not both integers".to_string())
+        );
+        // a function may take more and return less than is wanted
+        assert_eq!(
+            check("fn(f: (int -> top)) { f(1) }(fn(x: top) { 2 })"),
+            Ok(Type::Top)
+        );
+        assert_eq!(
+            check("fn(f: (top -> int)) { f(1) }(fn(x: int) { x })"),
+            Err("function argument type mismatch: expected (top -> int), \
+                 got (int -> int): in the parameter, top is not a subtype of int"
+                .to_string())
+        );
+        assert_eq!(
+            check("fn(f: ((int -> int) -> int)) { 1 }(fn(g: (top -> int)) { 2 })"),
+            Err("function argument type mismatch: \
+                 expected ((int -> int) -> int), got ((top -> int) -> int): \
+                 in the parameter, in the parameter, top is not a subtype of int"
+                .to_string())
+        );
+        // a conditional has the join of its branches' types
+        assert_eq!(
+            check("if true { fn(x: top) { 1 } } else { fn(x: int) { 2 } }"),
+            Ok(fun(Type::Int, Type::Int))
+        );
+        assert_eq!(
+            check(
+                "if true { fn(x: top) { (1 : top) } } else { fn(x: int) { 2 } }"
+            ),
+            Ok(fun(Type::Int, Type::Top))
+        );
+        assert_eq!(
+            check("if true { 1 } else { (\"a\" : top) }"),
+            Ok(Type::Top)
+        );
+        // and top if nothing less will do
+        assert_eq!(check("if true { 1 } else { \"a\" }"), Ok(Type::Top));
+        assert_eq!(
+            check("if true { fn(x: int) { 1 } } else { fn(x: str) { 2 } }"),
+            Ok(fun(Type::Never, Type::Int))
+        );
+        assert_eq!(
+            check("if true { 1 } else { \"a\" } + 1"),
+            Err("not both integers".to_string())
+        );
+        assert_eq!(
+            check("let x: top = if true { 1 } else { \"a\" } { x }"),
+            Ok(Type::Top)
+        );
+        assert_eq!(
+            check("if true { fn(x: int) { 1 } } else { fn(x: int) { \"a\" } }"),
+            Ok(fun(Type::Int, Type::Top))
+        );
+        assert_eq!(check("try { 1 } catch (e) { e }"), Ok(Type::Top));
+        assert_eq!(check("try { (1 : dyn) } catch (e) { e }"), Ok(Type::Str));
+        let run = |input: &str| {
+            let (e, _, _) = elaborate(&parse(input), &TEnv::new())?;
+            interp(&e, &Env::new())
+        };
+        assert_eq!(
+            run("fn(f: (int -> top)) { f(1) }(fn(x: top) { x })"),
+            Ok(Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn tc_conditional() {
+        assert_eq!(check("if true { 1 } else { 2 }"), Ok(Type::Int));
+        assert_eq!(
+            check("if 1 { 1 } else { 2 }"),
+            Err("condition must be a bool".to_string())
+        );
+        assert_eq!(check("if true { 1 } else { \"a\" }"), Ok(Type::Top));
+    }
+
+    #[test]
+    fn tc_let1() {
+        assert_eq!(check("let x = \"a\" { x ++ x }"), Ok(Type::Str));
+        assert_eq!(check("let x = 1 { let x = true { x } }"), Ok(Type::Bool));
+        assert_eq!(
+            check("let x = y { x }"),
+            Err("no known type for y".to_string())
+        );
+    }
+
+    #[test]
+    fn tc_lambda() {
+        assert_eq!(
+            check("fn(x: int) { x < 1 }"),
+            Ok(fun(Type::Int, Type::Bool))
+        );
+        assert_eq!(
+            check("fn(x: int) { fn(y: str) { x } }"),
+            Ok(fun(Type::Int, fun(Type::Str, Type::Int)))
+        );
+        // lambda bodies only see their parameter and enclosing bindings
+        assert_eq!(
+            check("let f = fn(y: int) { x } { let x = 1 { f(10) } }"),
+            Err("no known type for x".to_string())
+        );
+    }
+
+    #[test]
+    fn tc_application() {
+        assert_eq!(check("fn(x: int) { x }(1)"), Ok(Type::Int));
+        assert_eq!(
+            check("fn(f: (int -> int)) { f(10) }(fn(x: int) { x + 2 })"),
+            Ok(Type::Int)
+        );
+        assert_eq!(
+            check("fn(x: int) { x }(true)"),
+            Err("function argument type mismatch: expected int, got bool"
+                .to_string())
+        );
+        assert_eq!(
+            check("1(2)"),
+            Err("function expected, found int".to_string())
+        );
     }
 }
@@ -0,0 +1,461 @@
+pub mod cps;
+pub mod format;
+pub mod interp;
+pub mod parse;
+pub mod pretty;
+pub mod prims;
+pub mod repl;
+pub mod step;
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Int(isize),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Symbol(String),
+    Plus,
+    Concat,
+    LessThan,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    Colon,
+    Comma,
+    Semicolon,
+    RightArrow,
+    // -{, opening the effects of a function type
+    LeftEffect,
+    Equal,
+    If,
+    Else,
+    Let,
+    LetCC,
+    Fn,
+    Print,
+    Raise,
+    Try,
+    Catch,
+    Perform,
+    Handle,
+    With,
+    Return,
+    Effect,
+    Type,
+    IntType,
+    FloatType,
+    BoolType,
+    StrType,
+    UnitType,
+    ContType,
+    DynType,
+    TopType,
+    Comment(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Int(n) => write!(f, "{n}"),
+            Token::Float(x) => write!(f, "{x:?}"),
+            Token::Bool(b) => write!(f, "{b}"),
+            Token::Str(s) => write!(f, "{}", Quoted(s)),
+            Token::Symbol(s) => write!(f, "{s}"),
+            Token::Plus => write!(f, "+"),
+            Token::Concat => write!(f, "++"),
+            Token::LessThan => write!(f, "<"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::LeftBrace => write!(f, "{{"),
+            Token::RightBrace => write!(f, "}}"),
+            Token::Colon => write!(f, ":"),
+            Token::Comma => write!(f, ","),
+            Token::Semicolon => write!(f, ";"),
+            Token::RightArrow => write!(f, "->"),
+            Token::LeftEffect => write!(f, "-{{"),
+            Token::Equal => write!(f, "="),
+            Token::If => write!(f, "if"),
+            Token::Else => write!(f, "else"),
+            Token::Let => write!(f, "let"),
+            Token::LetCC => write!(f, "letcc"),
+            Token::Fn => write!(f, "fn"),
+            Token::Print => write!(f, "print"),
+            Token::Raise => write!(f, "raise"),
+            Token::Try => write!(f, "try"),
+            Token::Catch => write!(f, "catch"),
+            Token::Perform => write!(f, "perform"),
+            Token::Handle => write!(f, "handle"),
+            Token::With => write!(f, "with"),
+            Token::Return => write!(f, "return"),
+            Token::Effect => write!(f, "effect"),
+            Token::Type => write!(f, "type"),
+            Token::IntType => write!(f, "int"),
+            Token::FloatType => write!(f, "float"),
+            Token::BoolType => write!(f, "bool"),
+            Token::StrType => write!(f, "str"),
+            Token::UnitType => write!(f, "unit"),
+            Token::ContType => write!(f, "cont"),
+            Token::DynType => write!(f, "dyn"),
+            Token::TopType => write!(f, "top"),
+            Token::Comment(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Exp {
+    Int(isize),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Unit,
+    Var(String),
+    Plus { left: Rc<Exp>, right: Rc<Exp> },
+    Concat { left: Rc<Exp>, right: Rc<Exp> },
+    LessThan { left: Rc<Exp>, right: Rc<Exp> },
+    Cnd { tst: Rc<Exp>, thn: Rc<Exp>, els: Rc<Exp> },
+    Let1 { var: String, value: Rc<Exp>, body: Rc<Exp> },
+    // one parameter each: the parser reads fn(x: int, y: int) { e } as
+    // fn(x: int) { fn(y: int) { e } } and f(1, 2) as f(1)(2), so a call
+    // with fewer arguments than parameters gives the function of the rest;
+    // f() is f applied to the unit value, and fn() { e } is
+    // fn(_: unit) { e }. The body is shared with every
+    // closure made from it. var_type is None if the parameter isn't
+    // annotated, leaving the checker to take it from the function type
+    // expected there
+    Lam { var: String, var_type: Option<Type>, body: Rc<Exp> },
+    App { fun: Rc<Exp>, arg: Rc<Exp> },
+    Seq { first: Rc<Exp>, second: Rc<Exp> },
+    Print { arg: Rc<Exp> },
+    // (e : T), asserting that e has type T; let x: T = e { b } is read as
+    // let x = (e : T) { b }
+    Ascribe { exp: Rc<Exp>, typ: Type },
+    // letcc k: cont T { b } binds k to the continuation of the whole
+    // letcc expression, of type T; applying k to a value makes that the
+    // letcc's value at once, abandoning the rest of b
+    LetCC { var: String, var_type: Type, body: Rc<Exp> },
+    // raises arg, a str, as an exception; it takes a term, so raise x ++ y
+    // is (raise x) ++ y
+    Raise { arg: Rc<Exp> },
+    // the value of body, or if that raises, of handler with var bound to
+    // the exception; runtime errors raise their message, so they are
+    // caught too
+    Try { body: Rc<Exp>, var: String, handler: Rc<Exp> },
+    // performs the effect operation op on arg, getting back a value from
+    // whichever enclosing handler has a clause for op; param and result
+    // are the types its declaration, effect Op: A -> B, gives
+    Perform { op: String, arg: Rc<Exp>, param: Box<Type>, result: Box<Type> },
+    // the value of body, with the operations it performs handled by
+    // clauses, passed through the return clause if there is one
+    Handle { body: Rc<Exp>, clauses: Vec<Clause>, ret: Option<Return> },
+    // checks that the value of exp, of type from, is a to, blaming label,
+    // the source of exp, if not; only the type checker writes these
+    Cast { exp: Rc<Exp>, from: Box<Type>, to: Box<Type>, label: String },
+    // exp, read at pos in the input; the REPL's parser writes these so that
+    // a cast can say where its source was, and the type checker drops them
+    At { pos: Pos, exp: Rc<Exp> },
+}
+
+impl Exp {
+    // The expression without the positions wrapped around it.
+    pub fn bare(&self) -> &Exp {
+        match self {
+            Exp::At { exp, .. } => exp.bare(),
+            e => e,
+        }
+    }
+}
+
+// Where a token starts, counting lines and columns from 1.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// A handler clause, op(var, k) -> body: body runs in place of a perform
+// of op, with var bound to the argument and k to the rest of the handled
+// expression, which gives the clause a value by resuming it. The clause's
+// value is the handle expression's.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Clause {
+    pub op: String,
+    pub param: Type,
+    pub result: Type,
+    pub var: String,
+    pub k: String,
+    pub body: Rc<Exp>,
+}
+
+// A return clause, return var -> body, applied to the handled
+// expression's value.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Return {
+    pub var: String,
+    pub body: Rc<Exp>,
+}
+
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exp::Int(n) => write!(f, "{n}"),
+            Exp::Float(x) => write!(f, "{x:?}"),
+            Exp::Bool(b) => write!(f, "{b}"),
+            Exp::Str(s) => write!(f, "{}", Quoted(s)),
+            Exp::Unit => write!(f, "()"),
+            Exp::Var(v) => write!(f, "{v}"),
+            Exp::Plus { left, right } => write!(f, "(+ {left} {right})"),
+            Exp::Concat { left, right } => write!(f, "(++ {left} {right})"),
+            Exp::LessThan { left, right } => write!(f, "(< {left} {right})"),
+            Exp::Cnd { tst, thn, els } => write!(f, "(if {tst} {thn} {els})"),
+            Exp::Let1 { var, value, body } => {
+                write!(f, "(let {var} {value} {body})")
+            }
+            Exp::Lam { var, var_type: Some(var_type), body } => {
+                write!(f, "(fn ({var}: {var_type}) {body})")
+            }
+            Exp::Lam { var, var_type: None, body } => {
+                write!(f, "(fn ({var}) {body})")
+            }
+            Exp::App { fun, arg } => write!(f, "({fun} {arg})"),
+            Exp::Seq { first, second } => write!(f, "(; {first} {second})"),
+            Exp::Print { arg } => write!(f, "(print {arg})"),
+            Exp::Ascribe { exp, typ } => write!(f, "(: {exp} {typ})"),
+            Exp::LetCC { var, var_type, body } => {
+                write!(f, "(letcc ({var}: {var_type}) {body})")
+            }
+            Exp::Raise { arg } => write!(f, "(raise {arg})"),
+            Exp::Try { body, var, handler } => {
+                write!(f, "(try {body} {var} {handler})")
+            }
+            Exp::Perform { op, arg, .. } => write!(f, "(perform {op} {arg})"),
+            Exp::Handle { body, clauses, ret } => {
+                write!(f, "(handle {body}")?;
+                for Clause { op, var, k, body, .. } in clauses {
+                    write!(f, " ({op} {var} {k} {body})")?;
+                }
+                if let Some(Return { var, body }) = ret {
+                    write!(f, " (return {var} {body})")?;
+                }
+                write!(f, ")")
+            }
+            Exp::Cast { exp, from, to, .. } => {
+                write!(f, "(cast {from} {to} {exp})")
+            }
+            Exp::At { exp, .. } => write!(f, "{exp}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Int(isize),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    // what str->int gives: the integer read, or None if there wasn't one
+    IntOption(Option<isize>),
+    Unit,
+    Fun { var: String, var_type: Type, body: Rc<Exp>, nv: Env },
+    Prim { name: String, args: Vec<Value> },
+    // an argument not evaluated yet, bound in place of its value when
+    // evaluating lazily; the cache holds the value once call-by-need has
+    // computed it
+    Thunk { exp: Rc<Exp>, nv: Env, cache: Rc<RefCell<Option<Value>>> },
+    // the rest of a computation, captured by letcc in the CPS interpreter,
+    // and the continuation type the letcc gave it
+    Cont { typ: Type, frames: Vec<cps::Frame> },
+    // the rest of a handled expression from a perform, up to and including
+    // its handler; resuming it runs those frames and returns
+    Resume { frames: Vec<cps::Frame> },
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{n}"),
+            // Debug always shows a fraction or exponent, so 2.0 doesn't
+            // print like the int 2
+            Value::Float(x) => write!(f, "{x:?}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Str(s) => write!(f, "{s}"),
+            Value::IntOption(Some(n)) => write!(f, "some({n})"),
+            Value::IntOption(None) => write!(f, "none"),
+            Value::Unit => write!(f, "()"),
+            Value::Fun { var, var_type, body, nv } => {
+                write!(f, "closure((fn ({var}: {var_type}) {body}), {nv:?})")
+            }
+            Value::Prim { name, args } => {
+                write!(f, "primitive({name}")?;
+                for arg in args {
+                    match arg {
+                        Value::Str(s) => write!(f, " {}", Quoted(s))?,
+                        v => write!(f, " {v}")?,
+                    }
+                }
+                write!(f, ")")
+            }
+            Value::Thunk { exp, .. } => write!(f, "thunk({exp})"),
+            Value::Cont { .. } | Value::Resume { .. } => {
+                write!(f, "continuation")
+            }
+        }
+    }
+}
+
+pub type Env = HashMap<String, Value>;
+
+// Displays a string as a literal the tokenizer reads back, with quotes
+// and escapes.
+pub struct Quoted<'a>(pub &'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\t' => write!(f, "\\t")?,
+                c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+    // an int that may be missing, the result of str->int; there is no
+    // syntax for it, as only primitives make and take one
+    IntOption,
+    Unit,
+    // effects are what calling the function may do besides returning, as
+    // in (int -{Get, Put}-> int); a function will do for one that takes
+    // less and returns more
+    Fun { param: Box<Type>, result: Box<Type>, effects: EffectRow },
+    // a continuation expecting a value of type param
+    Cont { param: Box<Type> },
+    // the type of an expression that never returns a value, such as a
+    // raise; it fits wherever a value is expected
+    Never,
+    // the type of a value known only at run time; it is consistent with
+    // every type, and using it as one checks the value there
+    Dyn,
+    // the type every value has, so a value will do wherever one of a
+    // supertype of its own is wanted; a value of it can only be passed on
+    Top,
+    // a name given to a type by a type declaration
+    Alias { name: String, def: Box<Type> },
+}
+
+impl Type {
+    // The type an alias stands for, looking through aliases of aliases.
+    pub fn resolve(&self) -> &Type {
+        match self {
+            Type::Alias { def, .. } => def.resolve(),
+            t => t,
+        }
+    }
+}
+
+// Types are equal when their expansions are: an alias is the same type as
+// its definition.
+impl PartialEq for Type {
+    fn eq(&self, other: &Type) -> bool {
+        match (self.resolve(), other.resolve()) {
+            (
+                Type::Fun { param, result, effects },
+                Type::Fun {
+                    param: other_param,
+                    result: other_result,
+                    effects: other_effects,
+                },
+            ) => {
+                param == other_param
+                    && result == other_result
+                    && effects == other_effects
+            }
+            (Type::Cont { param }, Type::Cont { param: other_param }) => {
+                param == other_param
+            }
+            (Type::Int, Type::Int)
+            | (Type::Float, Type::Float)
+            | (Type::Bool, Type::Bool)
+            | (Type::Str, Type::Str)
+            | (Type::IntOption, Type::IntOption)
+            | (Type::Unit, Type::Unit)
+            | (Type::Never, Type::Never)
+            | (Type::Dyn, Type::Dyn)
+            | (Type::Top, Type::Top) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::IntOption => write!(f, "int?"),
+            Type::Unit => write!(f, "unit"),
+            Type::Fun { param, result, effects } if effects.is_empty() => {
+                write!(f, "({param} -> {result})")
+            }
+            Type::Fun { param, result, effects } => {
+                let effects: Vec<&str> =
+                    effects.iter().map(String::as_str).collect();
+                write!(f, "({param} -{{{}}}-> {result})", effects.join(", "))
+            }
+            Type::Cont { param } => write!(f, "cont {param}"),
+            Type::Never => write!(f, "never"),
+            Type::Dyn => write!(f, "dyn"),
+            Type::Top => write!(f, "top"),
+            Type::Alias { name, .. } => write!(f, "{name}"),
+        }
+    }
+}
+
+pub type TEnv = HashMap<String, Type>;
+
+// The type aliases in scope, by name.
+pub type Aliases = HashMap<String, Type>;
+
+// The effect operations declared, by name, with their argument and result
+// types.
+pub type Ops = HashMap<String, (Type, Type)>;
+
+// The effects a function may have, in order: the operations it may
+// perform, io if it may print, and effect variables.
+pub type EffectRow = BTreeSet<String>;
+
+// The effect of printing, which no handler handles.
+pub const IO: &str = "io";
+
+// Whether an effect in a row is a variable, standing for the effects of a
+// function passed in: a lowercase name other than io, since operation
+// names are capitalized.
+pub fn is_effect_var(name: &str) -> bool {
+    name != IO && name.starts_with(|c: char| c.is_ascii_lowercase())
+}
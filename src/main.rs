@@ -1,234 +1,121 @@
-mod interp;
-mod parse;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+use std::thread;
+use student::format::format_source;
+use student::interp::{STACK_SIZE, Strategy};
+use student::pretty::Printer;
+use student::repl::{Config, Syntax};
+
+const USAGE: &str = "usage: student [--syntax=surface|sexp]
+               [--strategy=value|name|need] [--cps] [--trace]
+       student fmt [--check] [file...]";
 
-use interp::interp;
-use interp::tc;
-use parse::parse_expression;
-use parse::tokenize;
-use std::collections::HashMap;
-use std::fmt;
-use std::io;
-
-#[derive(Debug, PartialEq)]
-enum Token {
-    Int(isize),
-    Bool(bool),
-    Str(String),
-    Symbol(String),
-    Plus,
-    Concat,
-    LessThan,
-    LeftParen,
-    RightParen,
-    LeftBrace,
-    RightBrace,
-    Colon,
-    RightArrow,
-    Equal,
-    If,
-    Else,
-    Let,
-    Fn,
-    IntType,
-    BoolType,
-    StrType,
-}
-
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Token::Int(n) => write!(f, "{n}"),
-            Token::Bool(b) => write!(f, "{b}"),
-            Token::Str(s) => write!(f, "\"{s}\""),
-            Token::Symbol(s) => write!(f, "{s}"),
-            Token::Plus => write!(f, "+"),
-            Token::Concat => write!(f, "++"),
-            Token::LessThan => write!(f, "<"),
-            Token::LeftParen => write!(f, "("),
-            Token::RightParen => write!(f, ")"),
-            Token::LeftBrace => write!(f, "{{"),
-            Token::RightBrace => write!(f, "}}"),
-            Token::Colon => write!(f, ":"),
-            Token::RightArrow => write!(f, "->"),
-            Token::Equal => write!(f, "="),
-            Token::If => write!(f, "if"),
-            Token::Else => write!(f, "else"),
-            Token::Let => write!(f, "let"),
-            Token::Fn => write!(f, "fn"),
-            Token::IntType => write!(f, "int"),
-            Token::BoolType => write!(f, "bool"),
-            Token::StrType => write!(f, "str"),
-        }
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("fmt") {
+        process::exit(fmt(&args[1..]));
     }
-}
 
-#[derive(Debug, PartialEq, Clone)]
-enum Exp {
-    Int(isize),
-    Bool(bool),
-    Str(String),
-    Var(String),
-    Plus { left: Box<Exp>, right: Box<Exp> },
-    Concat { left: Box<Exp>, right: Box<Exp> },
-    LessThan { left: Box<Exp>, right: Box<Exp> },
-    Cnd { tst: Box<Exp>, thn: Box<Exp>, els: Box<Exp> },
-    Let1 { var: String, value: Box<Exp>, body: Box<Exp> },
-    Lam { var: String, var_type: Type, body: Box<Exp> },
-    App { fun: Box<Exp>, arg: Box<Exp> },
-}
-
-impl fmt::Display for Exp {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Exp::Int(n) => write!(f, "{n}"),
-            Exp::Bool(b) => write!(f, "{b}"),
-            Exp::Str(s) => write!(f, "\"{s}\""),
-            Exp::Var(v) => write!(f, "{v}"),
-            Exp::Plus { left, right } => write!(f, "(+ {left} {right})"),
-            Exp::Concat { left, right } => write!(f, "(++ {left} {right})"),
-            Exp::LessThan { left, right } => write!(f, "(< {left} {right})"),
-            Exp::Cnd { tst, thn, els } => write!(f, "(if {tst} {thn} {els})"),
-            Exp::Let1 { var, value, body } => {
-                write!(f, "(let {var} {value} {body})")
-            }
-            Exp::Lam { var, var_type, body } => {
-                write!(f, "(fn ({var}: {var_type}) {body})")
-            }
-            Exp::App { fun, arg } => write!(f, "({fun} {arg})"),
+    let mut config = Config::default();
+    for arg in &args {
+        match arg.as_str() {
+            "--syntax=surface" => config.syntax = Syntax::Surface,
+            "--syntax=sexp" => config.syntax = Syntax::Sexp,
+            "--strategy=value" => config.strategy = Strategy::ByValue,
+            "--strategy=name" => config.strategy = Strategy::ByName,
+            "--strategy=need" => config.strategy = Strategy::ByNeed,
+            "--cps" => config.cps = true,
+            "--trace" => config.trace = true,
+            _ => usage(arg),
         }
     }
-}
 
-#[derive(Debug, PartialEq, Clone)]
-enum Value {
-    Int(isize),
-    Bool(bool),
-    Str(String),
-    Fun { var: String, var_type: Type, body: Box<Exp>, nv: Env },
-}
-
-impl fmt::Display for Value {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Int(n) => write!(f, "{n}"),
-            Value::Bool(b) => write!(f, "{b}"),
-            Value::Str(s) => write!(f, "{s}"),
-            Value::Fun { var, var_type, body, nv } => write!(
-                f,
-                "closure((fn ({var}: {var_type}) {body}), {nv:?})"
-            ),
-        }
+    // the CPS interpreter has no lazy strategies
+    if config.cps && config.strategy != Strategy::ByValue {
+        eprintln!("--cps can't be combined with --strategy\n{USAGE}");
+        process::exit(2);
     }
-}
 
-type Env = HashMap<String, Value>;
+    // evaluation recurses, so it gets a stack of its own, big enough for
+    // the depth the interpreter allows
+    let repl = move || {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+        student::repl::run_with(&config, stdin.lock(), &mut stdout)
+            .expect("Failed to read line");
+    };
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(repl)
+        .expect("Failed to start the REPL")
+        .join()
+        .expect("REPL panicked");
+}
 
-#[derive(Debug, PartialEq, Clone)]
-enum Type {
-    Int,
-    Bool,
-    Str,
-    Fun { param: Box<Type>, result: Box<Type> },
+fn usage(arg: &str) -> ! {
+    eprintln!("unknown argument: {arg}\n{USAGE}");
+    process::exit(2);
 }
 
-impl fmt::Display for Type {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Type::Int => write!(f, "int"),
-            Type::Bool => write!(f, "bool"),
-            Type::Str => write!(f, "str"),
-            Type::Fun { param, result } => write!(f, "({param} -> {result})"),
+// Format the given files in place, or stdin to stdout if there are none.
+// With --check, only report the files that are not formatted. Returns the
+// exit status.
+fn fmt(args: &[String]) -> i32 {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ if arg.starts_with('-') => usage(arg),
+            _ => files.push(arg.clone()),
         }
     }
-}
-
-type TEnv = HashMap<String, Type>;
-
-fn main() {
-    let empty_nv = Env::new();
-    let empty_tnv = TEnv::new();
-
-    loop {
-        // print a prompt
-        println!("\nPlease enter an expression:");
-        let mut tokens = Vec::new();
-
-        loop {
-            // read a line of input, quit on ctrl-d and skip empty lines
-            let mut input = String::new();
-            let len =
-                io::stdin().read_line(&mut input).expect("Failed to read line");
-            if len == 0 {
-                return;
-            }
-            if input.trim().is_empty() {
-                continue;
-            }
-
-            // tokenize
-            match tokenize(input.trim()) {
-                Ok(new_tokens) => {
-                    tokens.extend(new_tokens);
-                }
-                Err(msg) => {
-                    println!("Tokenizer error: {msg}");
-                    continue;
-                }
-            };
-
-            // scan the token list and count total nesting level
-            // we finish if we are at zero
-            let mut count = 0;
-            for elt in &tokens {
-                match elt {
-                    Token::LeftParen => count += 1,
-                    Token::RightParen => count -= 1,
-                    Token::LeftBrace => count += 1,
-                    Token::RightBrace => count -= 1,
-                    _ => {}
-                }
+    let printer = Printer::default();
+
+    if files.is_empty() {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src).expect("Failed to read input");
+        return match format_source(&src, &printer) {
+            Ok(formatted) if check && formatted != src => {
+                println!("<stdin> is not formatted");
+                1
             }
-            if count == 0 {
-                break;
+            Ok(_) if check => 0,
+            Ok(formatted) => {
+                print!("{formatted}");
+                0
             }
-        }
-
-        print!("tokens: [");
-        let mut sep = "";
-        for t in &tokens {
-            print!("{sep}{t}");
-            sep = ", ";
-        }
-        println!("]");
-
-        // parse
-        let ast = match parse_expression(&tokens) {
-            Ok(ast) => ast,
             Err(msg) => {
-                println!("Parse error: {msg}");
-                continue;
+                eprintln!("<stdin>: {msg}");
+                1
             }
         };
-        println!("ast   : {ast}");
+    }
 
-        // type check
-        let t = match tc(&ast, &empty_tnv) {
-            Ok(t) => t,
-            Err(msg) => {
-                println!("Type check failure: {msg}");
-                continue;
+    let mut status = 0;
+    for file in &files {
+        let result = fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|src| Ok((format_source(&src, &printer)?, src)));
+        match result {
+            Ok((formatted, src)) if formatted == src => {}
+            Ok(_) if check => {
+                println!("{file} is not formatted");
+                status = 1;
+            }
+            Ok((formatted, _)) => {
+                if let Err(e) = fs::write(file, formatted) {
+                    eprintln!("{file}: {e}");
+                    status = 1;
+                }
             }
-        };
-        println!("type  : {t}");
-
-        // evaluate
-        let v = match interp(&ast, &empty_nv) {
-            Ok(v) => v,
             Err(msg) => {
-                println!("Runtime error: {msg}");
-                continue;
+                eprintln!("{file}: {msg}");
+                status = 1;
             }
-        };
-        println!("result: {v}");
+        }
     }
+    status
 }
@@ -1,25 +1,187 @@
 use super::*;
+use std::iter::Peekable;
+use std::num::IntErrorKind;
+use std::str::Chars;
 
 pub fn parse_expression(tokens: &Vec<Token>) -> Result<Exp, String> {
-    let mut parser = Parser::new(tokens);
+    parse_expression_with(tokens, &Aliases::new(), &Ops::new())
+}
+
+// Parse an expression whose types may name the given aliases, and which
+// may perform and handle the given effect operations.
+pub fn parse_expression_with(
+    tokens: &Vec<Token>,
+    aliases: &Aliases,
+    ops: &Ops,
+) -> Result<Exp, String> {
+    parse_expression_at(tokens, &[], aliases, ops)
+}
+
+// Parse as parse_expression_with does, wrapping each expression in an
+// Exp::At from `positions`, which has one for each token.
+pub fn parse_expression_at(
+    tokens: &Vec<Token>,
+    positions: &[Pos],
+    aliases: &Aliases,
+    ops: &Ops,
+) -> Result<Exp, String> {
+    let mut parser = Parser::new(tokens, aliases, ops);
+    parser.positions = positions;
     let exp = parser.parse()?;
-    if parser.current_token().is_some() {
-        return Err("Expected to find end of input".to_string());
-    }
+    parser.expect_end()?;
+    Ok(exp)
+}
+
+// Parse the s-expression form that Exp's Display prints, e.g.
+// (let f (fn (x: int) (+ x 1)) (f 2)).
+pub fn parse_sexp(tokens: &Vec<Token>) -> Result<Exp, String> {
+    parse_sexp_with(tokens, &Aliases::new(), &Ops::new())
+}
+
+pub fn parse_sexp_with(
+    tokens: &Vec<Token>,
+    aliases: &Aliases,
+    ops: &Ops,
+) -> Result<Exp, String> {
+    parse_sexp_at(tokens, &[], aliases, ops)
+}
+
+// Parse as parse_sexp_with does, wrapping each expression in an Exp::At
+// from `positions`, which has one for each token.
+pub fn parse_sexp_at(
+    tokens: &Vec<Token>,
+    positions: &[Pos],
+    aliases: &Aliases,
+    ops: &Ops,
+) -> Result<Exp, String> {
+    let mut parser = Parser::new(tokens, aliases, ops);
+    parser.positions = positions;
+    let exp = parser.parse_sexp()?;
+    parser.expect_end()?;
     Ok(exp)
 }
 
+// Parse a type declaration, type Name = typeexp, returning the name and
+// the type it stands for. The same form is used with either syntax.
+pub fn parse_type_definition(
+    tokens: &Vec<Token>,
+    aliases: &Aliases,
+    ops: &Ops,
+) -> Result<(String, Type), String> {
+    let mut parser = Parser::new(tokens, aliases, ops);
+    parser.expect_token(&Token::Type)?;
+    let Some(Token::Symbol(s)) = parser.current_token() else {
+        return Err("Expected an identifier".to_string());
+    };
+    let name = s.clone();
+    parser.advance();
+    parser.expect_token(&Token::Equal)?;
+    let typ = parser.parse_typeexp()?;
+    parser.expect_end()?;
+    Ok((name, typ))
+}
+
+// Parse an effect declaration, effect Op: typeexp -> typeexp, returning
+// the operation's name and its argument and result types.
+pub fn parse_effect_declaration(
+    tokens: &Vec<Token>,
+    aliases: &Aliases,
+    ops: &Ops,
+) -> Result<(String, (Type, Type)), String> {
+    let mut parser = Parser::new(tokens, aliases, ops);
+    parser.expect_token(&Token::Effect)?;
+    let name = parser.parse_symbol()?;
+    // lowercase names in a row are effect variables
+    if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        return Err(format!("Effect names must be capitalized: {name}"));
+    }
+    parser.expect_token(&Token::Colon)?;
+    let param = parser.parse_typeexp()?;
+    parser.expect_token(&Token::RightArrow)?;
+    let result = parser.parse_typeexp()?;
+    parser.expect_end()?;
+    Ok((name, (param, result)))
+}
+
+// The error for a block comment still open at the end of the input, so
+// that line-at-a-time readers know to read on.
+pub const UNTERMINATED_COMMENT: &str = "unterminated comment";
+
+// Tokenize, dropping comments.
 pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    lex(input, 1, false).map(|(tokens, _)| tokens)
+}
+
+// Tokenize, keeping each comment as a Token::Comment so that tools like
+// the formatter can put them back.
+pub fn tokenize_with_comments(input: &str) -> Result<Vec<Token>, String> {
+    lex(input, 1, true).map(|(tokens, _)| tokens)
+}
+
+// Tokenize input that starts on line `line`, dropping comments, with
+// where each token starts.
+pub fn tokenize_at(
+    input: &str,
+    line: usize,
+) -> Result<(Vec<Token>, Vec<Pos>), String> {
+    lex(input, line, false)
+}
+
+// The characters left to lex, and the position of the next.
+#[derive(Clone)]
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    pos: Pos,
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        if ch == '\n' {
+            self.pos = Pos { line: self.pos.line + 1, column: 1 };
+        } else {
+            self.pos.column += 1;
+        }
+        Some(ch)
+    }
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn next_if(&mut self, f: impl FnOnce(&char) -> bool) -> Option<char> {
+        if f(self.peek()?) { self.next() } else { None }
+    }
+}
+
+fn lex(
+    input: &str,
+    line: usize,
+    keep_comments: bool,
+) -> Result<(Vec<Token>, Vec<Pos>), String> {
     let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
+    let mut positions = Vec::new();
+    let pos = Pos { line, column: 1 };
+    let mut chars = Cursor { chars: input.chars().peekable(), pos };
+    // a pass pushes a token at most, starting where the pass started
+    let mut start = pos;
 
     while let Some(&ch) = chars.peek() {
+        positions.resize(tokens.len(), start);
+        start = chars.pos;
         match ch {
             '0'..='9' | '-' => {
                 chars.next();
                 if chars.peek() == Some(&'>') {
                     tokens.push(Token::RightArrow);
                     chars.next();
+                } else if ch == '-' && chars.peek() == Some(&'{') {
+                    tokens.push(Token::LeftEffect);
+                    chars.next();
                 } else {
                     let mut int_str = String::new();
                     int_str.push(ch);
@@ -33,8 +195,35 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
                         }
                     }
 
+                    if int_str != "-"
+                        && lex_float_tail(&mut chars, &mut int_str)
+                    {
+                        match int_str.parse::<f64>() {
+                            Ok(x) if x.is_finite() => {
+                                tokens.push(Token::Float(x))
+                            }
+                            _ => {
+                                return Err(format!(
+                                    "Float literal out of range: {int_str}"
+                                ));
+                            }
+                        }
+                        continue;
+                    }
+
                     match int_str.parse::<isize>() {
                         Ok(i) => tokens.push(Token::Int(i)),
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                IntErrorKind::PosOverflow
+                                    | IntErrorKind::NegOverflow
+                            ) =>
+                        {
+                            return Err(format!(
+                                "Integer literal out of range: {int_str}"
+                            ));
+                        }
                         Err(_) => {
                             return Err(format!(
                                 "Invalid integer format: {}",
@@ -77,6 +266,14 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
                 tokens.push(Token::Colon);
                 chars.next();
             }
+            ',' => {
+                tokens.push(Token::Comma);
+                chars.next();
+            }
+            ';' => {
+                tokens.push(Token::Semicolon);
+                chars.next();
+            }
             '=' => {
                 tokens.push(Token::Equal);
                 chars.next();
@@ -88,14 +285,64 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
                     if ch == '"' {
                         break;
                     }
-                    s.push(ch);
                     chars.next();
+                    if ch == '\\' {
+                        s.push(lex_escape(&mut chars)?);
+                    } else {
+                        s.push(ch);
+                    }
                 }
                 if chars.next() != Some('"') {
-                    return Err(format!("unterminated string"));
+                    return Err("unterminated string".to_string());
                 }
                 tokens.push(Token::Str(s));
             }
+            '/' => {
+                let mut ahead = chars.clone();
+                ahead.next();
+                let comment = match ahead.peek() {
+                    Some('/') => {
+                        // line comment: up to the end of the line
+                        let mut text = String::new();
+                        while let Some(&ch) = chars.peek() {
+                            if ch == '\n' {
+                                break;
+                            }
+                            text.push(ch);
+                            chars.next();
+                        }
+                        text
+                    }
+                    Some('*') => {
+                        // block comment: up to the matching */, nesting
+                        let mut text = String::new();
+                        let mut depth = 0;
+                        while let Some(ch) = chars.next() {
+                            text.push(ch);
+                            let next = chars.peek().copied();
+                            if ch == '/' && next == Some('*') {
+                                depth += 1;
+                            } else if ch == '*' && next == Some('/') {
+                                depth -= 1;
+                            } else {
+                                continue;
+                            }
+                            text.extend(chars.next());
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        if depth != 0 {
+                            return Err(UNTERMINATED_COMMENT.to_string());
+                        }
+                        text
+                    }
+                    _ => return Err("Unexpected character: '/'".to_string()),
+                };
+                if keep_comments {
+                    tokens.push(Token::Comment(comment));
+                }
+            }
             c if c.is_whitespace() => {
                 chars.next();
             }
@@ -103,7 +350,13 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
                 // Parse identifiers and keywords
                 let mut ident_str = String::new();
                 while let Some(&ch) = chars.peek() {
-                    if ch.is_ascii_alphanumeric() || ch == '_' {
+                    // a hyphen joins two words, as in str-eq
+                    let joins = ch == '-' && {
+                        let mut ahead = chars.clone();
+                        ahead.next();
+                        ahead.peek().is_some_and(|c| c.is_ascii_alphabetic())
+                    };
+                    if ch.is_ascii_alphanumeric() || ch == '_' || joins {
                         ident_str.push(ch);
                         chars.next();
                     } else {
@@ -116,12 +369,28 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
                     "if" => tokens.push(Token::If),
                     "else" => tokens.push(Token::Else),
                     "let" => tokens.push(Token::Let),
+                    "letcc" => tokens.push(Token::LetCC),
                     "true" => tokens.push(Token::Bool(true)),
                     "false" => tokens.push(Token::Bool(false)),
                     "fn" => tokens.push(Token::Fn),
+                    "type" => tokens.push(Token::Type),
+                    "print" => tokens.push(Token::Print),
+                    "raise" => tokens.push(Token::Raise),
+                    "try" => tokens.push(Token::Try),
+                    "catch" => tokens.push(Token::Catch),
+                    "perform" => tokens.push(Token::Perform),
+                    "handle" => tokens.push(Token::Handle),
+                    "with" => tokens.push(Token::With),
+                    "return" => tokens.push(Token::Return),
+                    "effect" => tokens.push(Token::Effect),
                     "int" => tokens.push(Token::IntType),
+                    "float" => tokens.push(Token::FloatType),
                     "bool" => tokens.push(Token::BoolType),
                     "str" => tokens.push(Token::StrType),
+                    "unit" => tokens.push(Token::UnitType),
+                    "cont" => tokens.push(Token::ContType),
+                    "dyn" => tokens.push(Token::DynType),
+                    "top" => tokens.push(Token::TopType),
                     _ => tokens.push(Token::Symbol(ident_str)),
                 }
             }
@@ -131,91 +400,252 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
         }
     }
 
-    Ok(tokens)
+    positions.resize(tokens.len(), start);
+    Ok((tokens, positions))
+}
+
+// Read the fraction and exponent that make a number a float, as in 1.5,
+// 2e3 or 1.5e-3, appending them to `num`. Returns whether there were any.
+// A dot or an e only counts when digits follow it.
+fn lex_float_tail(chars: &mut Cursor, num: &mut String) -> bool {
+    let digits_after = |skip: usize, chars: &Cursor| {
+        let mut ahead = chars.clone();
+        ahead.nth(skip).is_some_and(|c| c.is_ascii_digit())
+    };
+    let digits = |chars: &mut Cursor, num: &mut String| {
+        while let Some(ch) = chars.next_if(char::is_ascii_digit) {
+            num.push(ch);
+        }
+    };
+
+    let mut is_float = false;
+    if chars.peek() == Some(&'.') && digits_after(1, chars) {
+        num.extend(chars.next());
+        digits(chars, num);
+        is_float = true;
+    }
+    if matches!(chars.peek(), Some('e' | 'E')) {
+        let mut ahead = chars.clone();
+        ahead.next();
+        let signed = matches!(ahead.peek(), Some('+' | '-'));
+        if digits_after(if signed { 2 } else { 1 }, chars) {
+            num.extend(chars.next());
+            if signed {
+                num.extend(chars.next());
+            }
+            digits(chars, num);
+            is_float = true;
+        }
+    }
+    is_float
+}
+
+// Read the rest of an escape sequence after its backslash: \n, \t, \",
+// \\ or \u{...} with one to six hex digits.
+fn lex_escape(chars: &mut Cursor) -> Result<char, String> {
+    match chars.next() {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('"') => Ok('"'),
+        Some('\\') => Ok('\\'),
+        Some('u') => {
+            if chars.next() != Some('{') {
+                return Err("Invalid unicode escape: expected '{'".to_string());
+            }
+            let mut hex = String::new();
+            for ch in chars.by_ref() {
+                if ch == '}' {
+                    let code = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .filter(|_| (1..=6).contains(&hex.len()));
+                    return code.and_then(char::from_u32).ok_or(format!(
+                        "Invalid unicode escape: \\u{{{hex}}}"
+                    ));
+                }
+                hex.push(ch);
+            }
+            Err("unterminated string".to_string())
+        }
+        Some(ch) => Err(format!("Invalid escape sequence: \\{ch}")),
+        None => Err("unterminated string".to_string()),
+    }
 }
 
+// Deepest expression the parser will build. The type checker, the
+// interpreter and Display all recurse over the tree, so this keeps
+// hostile input from overflowing the stack.
+const MAX_NESTING: usize = 200;
+
 struct Parser<'a> {
     tokens: &'a Vec<Token>,
+    // where each token starts, or nothing if expressions aren't wrapped
+    positions: &'a [Pos],
+    aliases: &'a Aliases,
+    ops: &'a Ops,
     position: usize,
+    depth: usize,
 }
 
 // grammar:
+// declaration      -> type symbol = typeexp | effect symbol : typeexp -> typeexp
+// sequence         -> expression [ ; expression ]*
 // expression       -> term [ (+ | ++ | <) term ]*
-// term             -> factor [ ( expression ) ]*
-// factor           -> ( expression ) | conditional | let1 | lambda | int | bool | str | symbol
-// conditional      -> if expression { expression } else { expression }
-// let1             -> let symbol = expression { expression }
-// lambda           -> fn ( symbol : typeexp ) { expression }
-// typeexp          -> num | bool | str | (typeexp -> typeexp)
+// term             -> factor [ ( expression [ , expression ]* ) | ( ) ]*
+// factor           -> ( sequence [ : typeexp ] ) | ( ) | conditional | let1 | letcc | lambda
+//                   | print | raise | try | perform | handle
+//                   | int | float | bool | str | symbol | conversion
+// conversion       -> int -> str | str -> int | int -> float | float -> int
+// conditional      -> if expression { sequence } else { sequence }
+// let1             -> let symbol [ : typeexp ] = expression { sequence }
+// letcc            -> letcc param { sequence }
+// lambda           -> fn ( [ lambda_param [ , lambda_param ]* ] ) { sequence }
+// lambda_param     -> symbol [ : typeexp ]
+// param            -> symbol : typeexp
+// print            -> print ( expression )
+// raise            -> raise term
+// try              -> try { sequence } catch ( symbol ) { sequence }
+// perform          -> perform symbol ( expression )
+// handle           -> handle expression with { handler [ ; handler ]* }
+// handler          -> symbol ( symbol , symbol ) -> expression
+//                   | return symbol -> expression
+// typeexp          -> num | float | bool | str | unit | dyn | top | symbol
+//                   | cont typeexp | (typeexp arrow typeexp)
+// arrow            -> -> | -{ symbol [ , symbol ]* }->
 
 impl<'a> Parser<'a> {
-    fn new(tokens: &'a Vec<Token>) -> Self {
-        Parser { tokens, position: 0 }
+    fn new(tokens: &'a Vec<Token>, aliases: &'a Aliases, ops: &'a Ops) -> Self {
+        let positions = &[];
+        Parser { tokens, positions, aliases, ops, position: 0, depth: 0 }
+    }
+
+    // Wrap `e`, read from the token at `start`, in that token's position
+    // if there is one and `e` isn't wrapped already. This is done in place,
+    // as the parser recurses deeply enough for its frames to matter.
+    fn wrap(&self, start: usize, e: &mut Exp) {
+        if let Some(&pos) = self.positions.get(start)
+            && !matches!(e, Exp::At { .. })
+        {
+            let exp = Rc::new(std::mem::replace(e, Exp::Unit));
+            *e = Exp::At { pos, exp };
+        }
     }
 
     fn parse(&mut self) -> Result<Exp, String> {
-        self.parse_expression()
+        self.parse_sequence()
+    }
+
+    fn parse_sequence(&mut self) -> Result<Exp, String> {
+        // e1; e2; e3 nests to the right, as e1; (e2; e3)
+        let (depth, start) = (self.depth, self.position);
+        let first = self.parse_expression()?;
+        if self.current_token() != Some(&Token::Semicolon) {
+            return Ok(first);
+        }
+        self.advance();
+        self.nest()?;
+        let second = self.parse_sequence()?;
+        self.depth = depth;
+        let mut seq =
+            Exp::Seq { first: Rc::new(first), second: Rc::new(second) };
+        self.wrap(start, &mut seq);
+        Ok(seq)
     }
 
     fn parse_expression(&mut self) -> Result<Exp, String> {
+        let (depth, start) = (self.depth, self.position);
+        self.nest()?;
         let mut left = self.parse_term()?;
 
         loop {
             match self.current_token() {
                 Some(Token::Plus) => {
+                    self.nest()?;
                     self.expect_token(&Token::Plus)?;
                     let right = self.parse_term()?;
                     left = Exp::Plus {
-                        left: Box::new(left),
-                        right: Box::new(right),
+                        left: Rc::new(left),
+                        right: Rc::new(right),
                     };
                 }
 
                 Some(Token::Concat) => {
+                    self.nest()?;
                     self.expect_token(&Token::Concat)?;
                     let right = self.parse_term()?;
                     left = Exp::Concat {
-                        left: Box::new(left),
-                        right: Box::new(right),
+                        left: Rc::new(left),
+                        right: Rc::new(right),
                     };
                 }
 
                 Some(Token::LessThan) => {
+                    self.nest()?;
                     self.expect_token(&Token::LessThan)?;
                     let right = self.parse_term()?;
                     left = Exp::LessThan {
-                        left: Box::new(left),
-                        right: Box::new(right),
+                        left: Rc::new(left),
+                        right: Rc::new(right),
                     };
                 }
 
                 _ => break,
             }
+            self.wrap(start, &mut left);
         }
 
+        self.depth = depth;
         Ok(left)
     }
 
     fn parse_term(&mut self) -> Result<Exp, String> {
+        let (depth, start) = (self.depth, self.position);
         let mut term = self.parse_factor()?;
 
         while let Some(&Token::LeftParen) = self.current_token() {
-            let fun = Box::new(term);
             self.expect_token(&Token::LeftParen)?;
-            let arg = Box::new(self.parse_expression()?);
+            if self.current_token() == Some(&Token::RightParen) {
+                self.advance();
+                self.nest()?;
+                self.wrap(start, &mut term);
+                let fun = Rc::new(term);
+                term = Exp::App { fun, arg: Rc::new(Exp::Unit) };
+                continue;
+            }
+            loop {
+                self.nest()?;
+                self.wrap(start, &mut term);
+                let fun = Rc::new(term);
+                let arg = Rc::new(self.parse_expression()?);
+                term = Exp::App { fun, arg };
+                if self.current_token() != Some(&Token::Comma) {
+                    break;
+                }
+                self.advance();
+            }
             self.expect_token(&Token::RightParen)?;
-            term = Exp::App { fun, arg };
         }
 
+        self.depth = depth;
+        self.wrap(start, &mut term);
         Ok(term)
     }
 
     fn parse_factor(&mut self) -> Result<Exp, String> {
         match self.current_token() {
             Some(Token::LeftParen) => {
-                // ( expr )
+                // ( ) or ( expr )
                 self.expect_token(&Token::LeftParen)?;
-                let expr = self.parse_expression()?;
+                if self.current_token() == Some(&Token::RightParen) {
+                    self.advance();
+                    return Ok(Exp::Unit);
+                }
+                let expr = self.parse_sequence()?;
+                if self.current_token() == Some(&Token::Colon) {
+                    self.advance();
+                    let typ = self.parse_typeexp()?;
+                    self.expect_token(&Token::RightParen)?;
+                    return Ok(Exp::Ascribe { exp: Rc::new(expr), typ });
+                }
                 self.expect_token(&Token::RightParen)?;
                 Ok(expr)
             }
@@ -224,14 +654,32 @@ impl<'a> Parser<'a> {
 
             Some(Token::Let) => self.parse_let1(),
 
+            Some(Token::LetCC) => self.parse_letcc(),
+
             Some(Token::Fn) => self.parse_lambda(),
 
+            Some(Token::Print) => self.parse_print(),
+
+            Some(Token::Raise) => self.parse_raise(),
+
+            Some(Token::Try) => self.parse_try(),
+
+            Some(Token::Perform) => self.parse_perform(),
+
+            Some(Token::Handle) => self.parse_handle(),
+
             Some(&Token::Int(n)) => {
                 self.advance();
 
                 Ok(Exp::Int(n))
             }
 
+            Some(&Token::Float(x)) => {
+                self.advance();
+
+                Ok(Exp::Float(x))
+            }
+
             Some(&Token::Bool(b)) => {
                 self.advance();
 
@@ -252,6 +700,10 @@ impl<'a> Parser<'a> {
                 Ok(var)
             }
 
+            Some(Token::IntType | Token::FloatType | Token::StrType) => {
+                self.parse_conversion().ok_or("Expected a factor".to_string())
+            }
+
             _ => Err("Expected a factor".to_string()),
         }
     }
@@ -259,59 +711,206 @@ impl<'a> Parser<'a> {
     fn parse_conditional(&mut self) -> Result<Exp, String> {
         // if cnd { thn } else { els }
         self.expect_token(&Token::If)?;
-        let tst = Box::new(self.parse_expression()?);
+        let tst = Rc::new(self.parse_expression()?);
         self.expect_token(&Token::LeftBrace)?;
-        let thn = Box::new(self.parse_expression()?);
+        let thn = Rc::new(self.parse_sequence()?);
         self.expect_token(&Token::RightBrace)?;
         self.expect_token(&Token::Else)?;
         self.expect_token(&Token::LeftBrace)?;
-        let els = Box::new(self.parse_expression()?);
+        let els = Rc::new(self.parse_sequence()?);
         self.expect_token(&Token::RightBrace)?;
         Ok(Exp::Cnd { tst, thn, els })
     }
 
     fn parse_let1(&mut self) -> Result<Exp, String> {
-        // let symbol = exp { exp }
+        // let symbol [: typeexp] = exp { exp }
         self.expect_token(&Token::Let)?;
         let Some(Token::Symbol(s)) = self.current_token() else {
-            return Err("Expected an indentifier".to_string());
+            return Err("Expected an identifier".to_string());
         };
         let var = s.clone();
         self.advance();
+        let typ = if self.current_token() == Some(&Token::Colon) {
+            self.advance();
+            Some(self.parse_typeexp()?)
+        } else {
+            None
+        };
         self.expect_token(&Token::Equal)?;
-        let value = Box::new(self.parse_expression()?);
+        let mut value = Rc::new(self.parse_expression()?);
+        if let Some(typ) = typ {
+            value = Rc::new(Exp::Ascribe { exp: value, typ });
+        }
         self.expect_token(&Token::LeftBrace)?;
-        let body = Box::new(self.parse_expression()?);
+        let body = Rc::new(self.parse_sequence()?);
         self.expect_token(&Token::RightBrace)?;
         Ok(Exp::Let1 { var, value, body })
     }
 
-    fn parse_lambda(&mut self) -> Result<Exp, String> {
-        // fn ( symbol : typeexp ) { exp }
-        self.expect_token(&Token::Fn)?;
+    fn parse_letcc(&mut self) -> Result<Exp, String> {
+        // letcc param { sequence }
+        self.expect_token(&Token::LetCC)?;
+        let (var, var_type) = self.parse_param()?;
+        self.expect_token(&Token::LeftBrace)?;
+        let body = Rc::new(self.parse_sequence()?);
+        self.expect_token(&Token::RightBrace)?;
+        Ok(Exp::LetCC { var, var_type, body })
+    }
+
+    fn parse_print(&mut self) -> Result<Exp, String> {
+        // print ( expression )
+        self.expect_token(&Token::Print)?;
+        self.expect_token(&Token::LeftParen)?;
+        let arg = Rc::new(self.parse_expression()?);
+        self.expect_token(&Token::RightParen)?;
+        Ok(Exp::Print { arg })
+    }
+
+    fn parse_raise(&mut self) -> Result<Exp, String> {
+        // raise term
+        let depth = self.depth;
+        self.nest()?;
+        self.expect_token(&Token::Raise)?;
+        let arg = Rc::new(self.parse_term()?);
+        self.depth = depth;
+        Ok(Exp::Raise { arg })
+    }
+
+    fn parse_try(&mut self) -> Result<Exp, String> {
+        // try { sequence } catch ( symbol ) { sequence }
+        self.expect_token(&Token::Try)?;
+        self.expect_token(&Token::LeftBrace)?;
+        let body = Rc::new(self.parse_sequence()?);
+        self.expect_token(&Token::RightBrace)?;
+        self.expect_token(&Token::Catch)?;
         self.expect_token(&Token::LeftParen)?;
         let Some(Token::Symbol(s)) = self.current_token() else {
-            return Err("Expected an indentifier".to_string());
+            return Err("Expected an identifier".to_string());
         };
         let var = s.clone();
         self.advance();
-        self.expect_token(&Token::Colon)?;
-        let param_type = self.parse_typeexp()?;
         self.expect_token(&Token::RightParen)?;
         self.expect_token(&Token::LeftBrace)?;
-        let body = Box::new(self.parse_expression()?);
+        let handler = Rc::new(self.parse_sequence()?);
+        self.expect_token(&Token::RightBrace)?;
+        Ok(Exp::Try { body, var, handler })
+    }
+
+    fn parse_perform(&mut self) -> Result<Exp, String> {
+        // perform op ( expression )
+        self.expect_token(&Token::Perform)?;
+        let (op, param, result) = self.parse_op()?;
+        self.expect_token(&Token::LeftParen)?;
+        let arg = Rc::new(self.parse_expression()?);
+        self.expect_token(&Token::RightParen)?;
+        let (param, result) = (Box::new(param), Box::new(result));
+        Ok(Exp::Perform { op, arg, param, result })
+    }
+
+    fn parse_handle(&mut self) -> Result<Exp, String> {
+        // handle expression with { handler [; handler]* }
+        self.expect_token(&Token::Handle)?;
+        let body = Rc::new(self.parse_expression()?);
+        self.expect_token(&Token::With)?;
+        self.expect_token(&Token::LeftBrace)?;
+        let mut clauses = Vec::new();
+        let mut ret = None;
+        loop {
+            if self.current_token() == Some(&Token::Return) {
+                // return symbol -> expression
+                self.advance();
+                let var = self.parse_symbol()?;
+                self.expect_token(&Token::RightArrow)?;
+                let body = Rc::new(self.parse_expression()?);
+                ret = Some(Return { var, body });
+            } else {
+                // op ( symbol , symbol ) -> expression
+                let (op, param, result) = self.parse_op()?;
+                self.expect_token(&Token::LeftParen)?;
+                let var = self.parse_symbol()?;
+                self.expect_token(&Token::Comma)?;
+                let k = self.parse_symbol()?;
+                self.expect_token(&Token::RightParen)?;
+                self.expect_token(&Token::RightArrow)?;
+                let body = Rc::new(self.parse_expression()?);
+                clauses.push(Clause { op, param, result, var, k, body });
+            }
+            if self.current_token() != Some(&Token::Semicolon) {
+                break;
+            }
+            self.advance();
+        }
+        self.expect_token(&Token::RightBrace)?;
+        Ok(Exp::Handle { body, clauses, ret })
+    }
+
+    fn parse_symbol(&mut self) -> Result<String, String> {
+        let Some(Token::Symbol(s)) = self.current_token() else {
+            return Err("Expected an identifier".to_string());
+        };
+        let var = s.clone();
+        self.advance();
+        Ok(var)
+    }
+
+    fn parse_lambda(&mut self) -> Result<Exp, String> {
+        // fn ( [symbol [: typeexp] [, symbol [: typeexp]]*] ) { exp }
+        let depth = self.depth;
+        self.expect_token(&Token::Fn)?;
+        self.expect_token(&Token::LeftParen)?;
+        let mut params = if self.current_token() == Some(&Token::RightParen) {
+            vec![("_".to_string(), Some(Type::Unit))]
+        } else {
+            vec![self.parse_lambda_param()?]
+        };
+        while self.current_token() == Some(&Token::Comma) {
+            self.advance();
+            self.nest()?;
+            params.push(self.parse_lambda_param()?);
+        }
+        self.expect_token(&Token::RightParen)?;
+        self.expect_token(&Token::LeftBrace)?;
+        let body = self.parse_sequence()?;
         self.expect_token(&Token::RightBrace)?;
-        Ok(Exp::Lam { var, var_type: param_type, body })
+        self.depth = depth;
+        Ok(curry(params, body))
+    }
+
+    fn parse_lambda_param(&mut self) -> Result<(String, Option<Type>), String> {
+        // symbol [: typeexp]
+        let var = self.parse_symbol()?;
+        if self.current_token() != Some(&Token::Colon) {
+            return Ok((var, None));
+        }
+        self.advance();
+        Ok((var, Some(self.parse_typeexp()?)))
+    }
+
+    fn parse_param(&mut self) -> Result<(String, Type), String> {
+        // symbol : typeexp
+        let Some(Token::Symbol(s)) = self.current_token() else {
+            return Err("Expected an identifier".to_string());
+        };
+        let var = s.clone();
+        self.advance();
+        self.expect_token(&Token::Colon)?;
+        Ok((var, self.parse_typeexp()?))
     }
 
     fn parse_typeexp(&mut self) -> Result<Type, String> {
-        // num | bool | str | (typeexp -> typeexp)
+        // num | float | bool | str | unit | dyn | top | symbol
+        // | cont typeexp | (typeexp arrow typeexp)
         match self.current_token() {
             Some(Token::IntType) => {
                 self.advance();
                 Ok(Type::Int)
             }
 
+            Some(Token::FloatType) => {
+                self.advance();
+                Ok(Type::Float)
+            }
+
             Some(Token::BoolType) => {
                 self.advance();
                 Ok(Type::Bool)
@@ -322,17 +921,119 @@ impl<'a> Parser<'a> {
                 Ok(Type::Str)
             }
 
+            Some(Token::UnitType) => {
+                self.advance();
+                Ok(Type::Unit)
+            }
+
+            Some(Token::DynType) => {
+                self.advance();
+                Ok(Type::Dyn)
+            }
+
+            Some(Token::TopType) => {
+                self.advance();
+                Ok(Type::Top)
+            }
+
+            Some(Token::ContType) => {
+                let depth = self.depth;
+                self.nest()?;
+                self.advance();
+                let param = Box::new(self.parse_typeexp()?);
+                self.depth = depth;
+                Ok(Type::Cont { param })
+            }
+
+            // a type alias keeps its name, for error messages
+            Some(Token::Symbol(name)) => match self.aliases.get(name) {
+                Some(def) => {
+                    let name = name.clone();
+                    let def = Box::new(def.clone());
+                    self.advance();
+                    Ok(Type::Alias { name, def })
+                }
+                None => Err(format!("Unknown type: {name}")),
+            },
+
             Some(Token::LeftParen) => {
+                let depth = self.depth;
+                self.nest()?;
                 self.expect_token(&Token::LeftParen)?;
                 let param = Box::new(self.parse_typeexp()?);
-                self.expect_token(&Token::RightArrow)?;
+                let effects = self.parse_arrow()?;
                 let result = Box::new(self.parse_typeexp()?);
                 self.expect_token(&Token::RightParen)?;
-                Ok(Type::Fun { param, result })
+                self.depth = depth;
+                Ok(Type::Fun { param, result, effects })
+            }
+
+            _ => Err("Expected a type".to_string()),
+        }
+    }
+
+    fn parse_arrow(&mut self) -> Result<EffectRow, String> {
+        // -> | -{ symbol [, symbol]* }->
+        let mut effects = EffectRow::new();
+        if self.current_token() == Some(&Token::LeftEffect) {
+            loop {
+                self.advance();
+                effects.insert(self.parse_effect()?);
+                if self.current_token() != Some(&Token::Comma) {
+                    break;
+                }
+            }
+            self.expect_token(&Token::RightBrace)?;
+        }
+        self.expect_token(&Token::RightArrow)?;
+        Ok(effects)
+    }
+
+    // An effect in a function type: io, a declared operation or an effect
+    // variable.
+    fn parse_effect(&mut self) -> Result<String, String> {
+        match self.current_token() {
+            Some(Token::Symbol(name)) if name == IO || is_effect_var(name) => {
+                self.parse_symbol()
             }
+            _ => Ok(self.parse_op()?.0),
+        }
+    }
 
-            _ => Err(format!("Expected a type")),
+    // The name of a declared effect operation, and its argument and result
+    // types.
+    fn parse_op(&mut self) -> Result<(String, Type, Type), String> {
+        let Some(Token::Symbol(name)) = self.current_token() else {
+            return Err("Expected an identifier".to_string());
+        };
+        let Some((param, result)) = self.ops.get(name) else {
+            return Err(format!("Unknown effect: {name}"));
+        };
+        let op = (name.clone(), param.clone(), result.clone());
+        self.advance();
+        Ok(op)
+    }
+
+    // A conversion primitive is named by its types, as in int->str, which
+    // tokenizes as a type, an arrow and a type.
+    fn parse_conversion(&mut self) -> Option<Exp> {
+        let [from, Token::RightArrow, to] =
+            self.tokens.get(self.position..self.position + 3)?
+        else {
+            return None;
+        };
+        let name = format!("{from}->{to}");
+        prims::prim_type(&name)?;
+        self.position += 3;
+        Some(Exp::Var(name))
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err("Expression nested too deeply".to_string());
         }
+        Ok(())
     }
 
     fn expect_token(&mut self, expected: &Token) -> Result<(), String> {
@@ -344,6 +1045,13 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn expect_end(&self) -> Result<(), String> {
+        match self.current_token() {
+            Some(_) => Err("Expected to find end of input".to_string()),
+            None => Ok(()),
+        }
+    }
+
     fn current_token(&self) -> Option<&Token> {
         self.tokens.get(self.position)
     }
@@ -352,3 +1060,1170 @@ impl<'a> Parser<'a> {
         self.position += 1;
     }
 }
+
+// Nest a function body in one lambda per parameter, the first outermost.
+fn curry(params: Vec<(String, Option<Type>)>, body: Exp) -> Exp {
+    params.into_iter().rev().fold(body, |body, (var, var_type)| Exp::Lam {
+        var,
+        var_type,
+        body: Rc::new(body),
+    })
+}
+
+// s-expression grammar:
+// sexp             -> int | float | bool | str | symbol | conversion | ( )
+//                   | ( form )
+// form             -> + sexp sexp | ++ sexp sexp | < sexp sexp | ; sexp sexp
+//                   | print sexp | raise sexp | : sexp typeexp
+//                   | if sexp sexp sexp
+//                   | let symbol sexp sexp
+//                   | letcc ( symbol : typeexp ) sexp
+//                   | try sexp symbol sexp
+//                   | perform symbol sexp
+//                   | handle sexp [ ( symbol symbol symbol sexp ) ]*
+//                     [ ( return symbol sexp ) ]
+//                   | fn ( symbol [ : typeexp ] ) [ ( symbol : typeexp ) ]* sexp
+//                   | sexp sexp [ sexp ]*
+
+impl Parser<'_> {
+    fn parse_sexp(&mut self) -> Result<Exp, String> {
+        let start = self.position;
+        let mut exp = self.parse_sexp_bare()?;
+        self.wrap(start, &mut exp);
+        Ok(exp)
+    }
+
+    fn parse_sexp_bare(&mut self) -> Result<Exp, String> {
+        match self.current_token() {
+            Some(Token::LeftParen) => {
+                let depth = self.depth;
+                self.nest()?;
+                self.expect_token(&Token::LeftParen)?;
+                if self.current_token() == Some(&Token::RightParen) {
+                    self.advance();
+                    self.depth = depth;
+                    return Ok(Exp::Unit);
+                }
+                let exp = self.parse_form()?;
+                self.expect_token(&Token::RightParen)?;
+                self.depth = depth;
+                Ok(exp)
+            }
+
+            Some(&Token::Int(n)) => {
+                self.advance();
+                Ok(Exp::Int(n))
+            }
+
+            Some(&Token::Float(x)) => {
+                self.advance();
+                Ok(Exp::Float(x))
+            }
+
+            Some(&Token::Bool(b)) => {
+                self.advance();
+                Ok(Exp::Bool(b))
+            }
+
+            Some(Token::Str(s)) => {
+                let ss = s.clone();
+                self.advance();
+                Ok(Exp::Str(ss))
+            }
+
+            Some(Token::Symbol(s)) => {
+                let var = Exp::Var(s.clone());
+                self.advance();
+                Ok(var)
+            }
+
+            Some(Token::IntType | Token::FloatType | Token::StrType) => self
+                .parse_conversion()
+                .ok_or("Expected an s-expression".to_string()),
+
+            _ => Err("Expected an s-expression".to_string()),
+        }
+    }
+
+    fn parse_form(&mut self) -> Result<Exp, String> {
+        match self.current_token() {
+            Some(Token::Plus) => {
+                let (left, right) = self.parse_two_sexps()?;
+                Ok(Exp::Plus { left, right })
+            }
+
+            Some(Token::Concat) => {
+                let (left, right) = self.parse_two_sexps()?;
+                Ok(Exp::Concat { left, right })
+            }
+
+            Some(Token::LessThan) => {
+                let (left, right) = self.parse_two_sexps()?;
+                Ok(Exp::LessThan { left, right })
+            }
+
+            Some(Token::Semicolon) => {
+                let (first, second) = self.parse_two_sexps()?;
+                Ok(Exp::Seq { first, second })
+            }
+
+            Some(Token::Print) => {
+                self.advance();
+                let arg = Rc::new(self.parse_sexp()?);
+                Ok(Exp::Print { arg })
+            }
+
+            Some(Token::Raise) => {
+                self.advance();
+                let arg = Rc::new(self.parse_sexp()?);
+                Ok(Exp::Raise { arg })
+            }
+
+            Some(Token::Colon) => {
+                self.advance();
+                let exp = Rc::new(self.parse_sexp()?);
+                let typ = self.parse_typeexp()?;
+                Ok(Exp::Ascribe { exp, typ })
+            }
+
+            Some(Token::If) => {
+                self.advance();
+                let tst = Rc::new(self.parse_sexp()?);
+                let thn = Rc::new(self.parse_sexp()?);
+                let els = Rc::new(self.parse_sexp()?);
+                Ok(Exp::Cnd { tst, thn, els })
+            }
+
+            Some(Token::Let) => {
+                self.advance();
+                let Some(Token::Symbol(s)) = self.current_token() else {
+                    return Err("Expected an identifier".to_string());
+                };
+                let var = s.clone();
+                self.advance();
+                let value = Rc::new(self.parse_sexp()?);
+                let body = Rc::new(self.parse_sexp()?);
+                Ok(Exp::Let1 { var, value, body })
+            }
+
+            Some(Token::LetCC) => self.parse_letcc_form(),
+
+            Some(Token::Try) => self.parse_try_form(),
+
+            Some(Token::Perform) => self.parse_perform_form(),
+
+            Some(Token::Handle) => self.parse_handle_form(),
+
+            Some(Token::Fn) => self.parse_fn_form(),
+
+            _ => {
+                let mut fun = self.parse_sexp()?;
+                loop {
+                    let arg = Rc::new(self.parse_sexp()?);
+                    fun = Exp::App { fun: Rc::new(fun), arg };
+                    if self.current_token() == Some(&Token::RightParen) {
+                        break;
+                    }
+                    self.nest()?;
+                }
+                Ok(fun)
+            }
+        }
+    }
+
+    // These are kept out of parse_form, which recurses once per level of
+    // nesting, so that their locals don't add to every level's stack
+    // frame.
+
+    // The two operands of a binary form, after its operator.
+    fn parse_two_sexps(&mut self) -> Result<(Rc<Exp>, Rc<Exp>), String> {
+        self.advance();
+        let first = Rc::new(self.parse_sexp()?);
+        let second = Rc::new(self.parse_sexp()?);
+        Ok((first, second))
+    }
+
+    fn parse_letcc_form(&mut self) -> Result<Exp, String> {
+        self.advance();
+        self.expect_token(&Token::LeftParen)?;
+        let (var, var_type) = self.parse_param()?;
+        self.expect_token(&Token::RightParen)?;
+        let body = Rc::new(self.parse_sexp()?);
+        Ok(Exp::LetCC { var, var_type, body })
+    }
+
+    fn parse_fn_form(&mut self) -> Result<Exp, String> {
+        self.advance();
+        // the first parameter may be unannotated, as in ( symbol );
+        // after it, a parameter is ( symbol : ..., anything else the
+        // body
+        self.nest()?;
+        self.expect_token(&Token::LeftParen)?;
+        let mut params = vec![self.parse_lambda_param()?];
+        self.expect_token(&Token::RightParen)?;
+        loop {
+            let at_param = matches!(
+                self.tokens.get(self.position..self.position + 3),
+                Some([Token::LeftParen, Token::Symbol(_), Token::Colon])
+            );
+            if !at_param {
+                break;
+            }
+            self.nest()?;
+            self.expect_token(&Token::LeftParen)?;
+            let (var, var_type) = self.parse_param()?;
+            params.push((var, Some(var_type)));
+            self.expect_token(&Token::RightParen)?;
+        }
+        let body = self.parse_sexp()?;
+        Ok(curry(params, body))
+    }
+
+    fn parse_try_form(&mut self) -> Result<Exp, String> {
+        self.advance();
+        let body = Rc::new(self.parse_sexp()?);
+        let Some(Token::Symbol(s)) = self.current_token() else {
+            return Err("Expected an identifier".to_string());
+        };
+        let var = s.clone();
+        self.advance();
+        let handler = Rc::new(self.parse_sexp()?);
+        Ok(Exp::Try { body, var, handler })
+    }
+
+    fn parse_perform_form(&mut self) -> Result<Exp, String> {
+        self.advance();
+        let (op, param, result) = self.parse_op()?;
+        let arg = Rc::new(self.parse_sexp()?);
+        let (param, result) = (Box::new(param), Box::new(result));
+        Ok(Exp::Perform { op, arg, param, result })
+    }
+
+    fn parse_handle_form(&mut self) -> Result<Exp, String> {
+        self.advance();
+        let body = Rc::new(self.parse_sexp()?);
+        let mut clauses = Vec::new();
+        let mut ret = None;
+        while ret.is_none() && self.current_token() == Some(&Token::LeftParen) {
+            self.advance();
+            if self.current_token() == Some(&Token::Return) {
+                self.advance();
+                let var = self.parse_symbol()?;
+                let body = Rc::new(self.parse_sexp()?);
+                ret = Some(Return { var, body });
+            } else {
+                let (op, param, result) = self.parse_op()?;
+                let var = self.parse_symbol()?;
+                let k = self.parse_symbol()?;
+                let body = Rc::new(self.parse_sexp()?);
+                clauses.push(Clause { op, param, result, var, k, body });
+            }
+            self.expect_token(&Token::RightParen)?;
+        }
+        Ok(Exp::Handle { body, clauses, ret })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Exp, String> {
+        parse_expression(&tokenize(input)?)
+    }
+
+    fn int(n: isize) -> Rc<Exp> {
+        Rc::new(Exp::Int(n))
+    }
+
+    fn var(v: &str) -> Rc<Exp> {
+        Rc::new(Exp::Var(v.to_string()))
+    }
+
+    fn plus(left: Rc<Exp>, right: Rc<Exp>) -> Rc<Exp> {
+        Rc::new(Exp::Plus { left, right })
+    }
+
+    #[test]
+    fn tokenize_literals() {
+        assert_eq!(
+            tokenize("42 -7 9223372036854775807 -9223372036854775808 true false \"hi there\" \"\"").unwrap(),
+            vec![
+                Token::Int(42),
+                Token::Int(-7),
+                Token::Int(isize::MAX),
+                Token::Int(isize::MIN),
+                Token::Bool(true),
+                Token::Bool(false),
+                Token::Str("hi there".to_string()),
+                Token::Str(String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_symbols_and_keywords() {
+        assert_eq!(
+            tokenize("if else let fn int bool str x_1 _y iff").unwrap(),
+            vec![
+                Token::If,
+                Token::Else,
+                Token::Let,
+                Token::Fn,
+                Token::IntType,
+                Token::BoolType,
+                Token::StrType,
+                Token::Symbol("x_1".to_string()),
+                Token::Symbol("_y".to_string()),
+                Token::Symbol("iff".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_punctuation() {
+        assert_eq!(
+            tokenize("+ ++ < ( ) { } : , -> =").unwrap(),
+            vec![
+                Token::Plus,
+                Token::Concat,
+                Token::LessThan,
+                Token::LeftParen,
+                Token::RightParen,
+                Token::LeftBrace,
+                Token::RightBrace,
+                Token::Colon,
+                Token::Comma,
+                Token::RightArrow,
+                Token::Equal,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_without_whitespace() {
+        assert_eq!(
+            tokenize("a+-2++\"s\"").unwrap(),
+            vec![
+                Token::Symbol("a".to_string()),
+                Token::Plus,
+                Token::Int(-2),
+                Token::Concat,
+                Token::Str("s".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_floats() {
+        assert_eq!(
+            tokenize("1.5 -2e3 0.25E+2 1e-3 3.0").unwrap(),
+            vec![
+                Token::Float(1.5),
+                Token::Float(-2000.0),
+                Token::Float(25.0),
+                Token::Float(0.001),
+                Token::Float(3.0),
+            ]
+        );
+        // a dot or an e without digits after it isn't part of the number
+        assert_eq!(
+            tokenize("1else").unwrap(),
+            vec![Token::Int(1), Token::Else]
+        );
+        assert_eq!(
+            tokenize("1.").unwrap_err(),
+            "Unexpected character: '.'".to_string()
+        );
+        assert_eq!(
+            tokenize("1e999"),
+            Err("Float literal out of range: 1e999".to_string())
+        );
+        assert_eq!(tokenize("float").unwrap(), vec![Token::FloatType]);
+        let shown: Vec<String> = tokenize("1.5 -2e3 1e20 float")
+            .unwrap()
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(shown, vec!["1.5", "-2000.0", "1e20", "float"]);
+    }
+
+    #[test]
+    fn tokenize_string_escapes() {
+        assert_eq!(
+            tokenize(r#""a\"b\\c\nd\te\u{e9}\u{1F600}""#).unwrap(),
+            vec![Token::Str("a\"b\\c\nd\te\u{e9}\u{1F600}".to_string())]
+        );
+        assert_eq!(
+            tokenize(r#""\q""#),
+            Err("Invalid escape sequence: \\q".to_string())
+        );
+        assert_eq!(
+            tokenize(r#""\u41""#),
+            Err("Invalid unicode escape: expected '{'".to_string())
+        );
+        assert_eq!(
+            tokenize(r#""\u{110000}""#),
+            Err("Invalid unicode escape: \\u{110000}".to_string())
+        );
+        assert_eq!(
+            tokenize(r#""\u{}""#),
+            Err("Invalid unicode escape: \\u{}".to_string())
+        );
+        assert_eq!(tokenize(r#""\""#), Err("unterminated string".to_string()));
+    }
+
+    #[test]
+    fn tokenize_hyphenated_symbols() {
+        assert_eq!(
+            tokenize("str-eq a-b-c int->str x -1").unwrap(),
+            vec![
+                Token::Symbol("str-eq".to_string()),
+                Token::Symbol("a-b-c".to_string()),
+                Token::IntType,
+                Token::RightArrow,
+                Token::StrType,
+                Token::Symbol("x".to_string()),
+                Token::Int(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert_eq!(tokenize("-"), Err("Invalid integer format: -".to_string()));
+        assert_eq!(
+            tokenize("- 1"),
+            Err("Invalid integer format: -".to_string())
+        );
+        assert_eq!(tokenize("\"abc"), Err("unterminated string".to_string()));
+        assert_eq!(
+            tokenize("99999999999999999999"),
+            Err("Integer literal out of range: 99999999999999999999"
+                .to_string())
+        );
+        assert_eq!(
+            tokenize("-170141183460469231731687303715884105728"),
+            Err("Integer literal out of range: \
+                 -170141183460469231731687303715884105728"
+                .to_string())
+        );
+        assert_eq!(tokenize("#"), Err("Unexpected character: '#'".to_string()));
+        assert_eq!(tokenize("é"), Err("Unexpected character: 'é'".to_string()));
+    }
+
+    #[test]
+    fn tokenize_at_records_where_tokens_start() {
+        let input = "let x = 10 {\n  x /* a\n */ + 1 }";
+        let (tokens, positions) = tokenize_at(input, 3).unwrap();
+        assert_eq!(tokens, tokenize(input).unwrap());
+        let at = |line, column| Pos { line, column };
+        assert_eq!(
+            positions,
+            vec![
+                at(3, 1),
+                at(3, 5),
+                at(3, 7),
+                at(3, 9),
+                at(3, 12),
+                at(4, 3),
+                at(5, 5),
+                at(5, 7),
+                at(5, 9),
+            ]
+        );
+        // each expression is wrapped in the position of its first token
+        let e = parse_expression_at(
+            &tokens,
+            &positions,
+            &Aliases::new(),
+            &Ops::new(),
+        )
+        .unwrap();
+        assert_eq!(e.to_string(), "(let x 10 (+ x 1))");
+        let Exp::At { pos, exp } = &e else { panic!("{e:?}") };
+        assert_eq!(*pos, at(3, 1));
+        let Exp::Let1 { body, .. } = &**exp else { panic!("{exp:?}") };
+        assert!(matches!(**body, Exp::At { pos, .. } if pos == at(4, 3)));
+    }
+
+    #[test]
+    fn tokenize_comments() {
+        let src = "1 // one\n+ /* two /* nested */ still */ 2 /**/";
+        assert_eq!(
+            tokenize(src).unwrap(),
+            vec![Token::Int(1), Token::Plus, Token::Int(2)]
+        );
+        assert_eq!(
+            tokenize_with_comments(src).unwrap(),
+            vec![
+                Token::Int(1),
+                Token::Comment("// one".to_string()),
+                Token::Plus,
+                Token::Comment("/* two /* nested */ still */".to_string()),
+                Token::Int(2),
+                Token::Comment("/**/".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("/* a\nb */x").unwrap(),
+            vec![Token::Symbol("x".to_string())]
+        );
+        assert_eq!(tokenize("//").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn tokenize_comment_errors() {
+        assert_eq!(tokenize("/* a"), Err(UNTERMINATED_COMMENT.to_string()));
+        assert_eq!(
+            tokenize("/* /* a */"),
+            Err(UNTERMINATED_COMMENT.to_string())
+        );
+        assert_eq!(tokenize("/*/"), Err(UNTERMINATED_COMMENT.to_string()));
+        assert_eq!(
+            tokenize("1 / 2"),
+            Err("Unexpected character: '/'".to_string())
+        );
+        assert_eq!(
+            tokenize("*/"),
+            Err("Unexpected character: '*'".to_string())
+        );
+    }
+
+    #[test]
+    fn token_display() {
+        let tokens = tokenize("if x < 1 { \"a\" ++ \"b\" } else { true }");
+        let shown: Vec<String> =
+            tokens.unwrap().iter().map(|t| t.to_string()).collect();
+        assert_eq!(
+            shown,
+            vec![
+                "if", "x", "<", "1", "{", "\"a\"", "++", "\"b\"", "}", "else",
+                "{", "true", "}"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_atoms() {
+        assert_eq!(parse("7"), Ok(Exp::Int(7)));
+        assert_eq!(parse("-7"), Ok(Exp::Int(-7)));
+        assert_eq!(parse("false"), Ok(Exp::Bool(false)));
+        assert_eq!(parse("\"s\""), Ok(Exp::Str("s".to_string())));
+        assert_eq!(parse("x"), Ok(Exp::Var("x".to_string())));
+        assert_eq!(parse("((x))"), Ok(Exp::Var("x".to_string())));
+    }
+
+    #[test]
+    fn parse_binary_operators_associate_left() {
+        assert_eq!(
+            parse("1 + 2 + 3"),
+            Ok(Rc::unwrap_or_clone(plus(plus(int(1), int(2)), int(3))))
+        );
+        assert_eq!(
+            parse("1 + (2 + 3)"),
+            Ok(Rc::unwrap_or_clone(plus(int(1), plus(int(2), int(3)))))
+        );
+        assert_eq!(
+            parse("1 < 2 + 3"),
+            Ok(Exp::Plus {
+                left: Rc::new(Exp::LessThan { left: int(1), right: int(2) }),
+                right: int(3),
+            })
+        );
+        assert_eq!(
+            parse("a ++ b"),
+            Ok(Exp::Concat { left: var("a"), right: var("b") })
+        );
+    }
+
+    #[test]
+    fn parse_conditional() {
+        assert_eq!(
+            parse("if b { 1 } else { 2 }"),
+            Ok(Exp::Cnd { tst: var("b"), thn: int(1), els: int(2) })
+        );
+    }
+
+    #[test]
+    fn parse_let1() {
+        assert_eq!(
+            parse("let x = 1 { x + x }"),
+            Ok(Exp::Let1 {
+                var: "x".to_string(),
+                value: int(1),
+                body: plus(var("x"), var("x")),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_lambda_and_types() {
+        assert_eq!(
+            parse("fn(f: (int -> (bool -> str))) { f }"),
+            Ok(Exp::Lam {
+                var: "f".to_string(),
+                var_type: Some(Type::Fun {
+                    param: Box::new(Type::Int),
+                    result: Box::new(Type::Fun {
+                        param: Box::new(Type::Bool),
+                        result: Box::new(Type::Str),
+                        effects: EffectRow::new(),
+                    }),
+                    effects: EffectRow::new(),
+                }),
+                body: var("f"),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_several_arguments_curries() {
+        assert_eq!(
+            parse("fn(x: int, y: str) { y }"),
+            Ok(Exp::Lam {
+                var: "x".to_string(),
+                var_type: Some(Type::Int),
+                body: Rc::new(Exp::Lam {
+                    var: "y".to_string(),
+                    var_type: Some(Type::Str),
+                    body: var("y"),
+                }),
+            })
+        );
+        // a parameter's type may be left for the checker to find
+        assert_eq!(
+            parse("fn(x, y: str) { y }"),
+            Ok(Exp::Lam {
+                var: "x".to_string(),
+                var_type: None,
+                body: Rc::new(Exp::Lam {
+                    var: "y".to_string(),
+                    var_type: Some(Type::Str),
+                    body: var("y"),
+                }),
+            })
+        );
+        assert_eq!(
+            parse_sexp(&tokenize("(fn (x) (fn (y) (x y)))").unwrap()),
+            parse("fn(x, y) { x(y) }")
+        );
+        assert_eq!(parse("f(1, x + 2)"), parse("f(1)(x + 2)"));
+        assert_eq!(parse("f(1, 2)(3)"), parse("f(1)(2)(3)"));
+        assert_eq!(parse("f(1,)"), Err("Expected a factor".to_string()));
+        assert_eq!(
+            parse("fn(x: int,) { x }"),
+            Err("Expected an identifier".to_string())
+        );
+        assert_eq!(
+            parse("fn(x: int y: int) { x }"),
+            Err("Expected 'RightParen' token".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_unit_sequences_and_print() {
+        let unit = || Rc::new(Exp::Unit);
+        assert_eq!(parse("()"), Ok(Exp::Unit));
+        assert_eq!(
+            parse("f()()"),
+            Ok(Exp::App {
+                fun: Rc::new(Exp::App { fun: var("f"), arg: unit() }),
+                arg: unit(),
+            })
+        );
+        assert_eq!(
+            parse("print(1); x; 2"),
+            Ok(Exp::Seq {
+                first: Rc::new(Exp::Print { arg: int(1) }),
+                second: Rc::new(Exp::Seq { first: var("x"), second: int(2) }),
+            })
+        );
+        // sequences are allowed in blocks and parentheses only
+        assert_eq!(parse("let x = 1 { x; x }"), parse("let x = 1 { (x; x) }"));
+        assert_eq!(
+            parse("f(x; x)"),
+            Err("Expected 'RightParen' token".to_string())
+        );
+        assert_eq!(
+            parse("fn(u: unit) { u }"),
+            Ok(Exp::Lam {
+                var: "u".to_string(),
+                var_type: Some(Type::Unit),
+                body: var("u"),
+            })
+        );
+        // a function of no parameters takes the unit value, as f() passes
+        assert_eq!(
+            parse("fn() { 1 }"),
+            Ok(Exp::Lam {
+                var: "_".to_string(),
+                var_type: Some(Type::Unit),
+                body: Rc::new(Exp::Int(1)),
+            })
+        );
+        assert_eq!(
+            parse("fn(, x) { 1 }"),
+            Err("Expected an identifier".to_string())
+        );
+        assert_eq!(
+            parse("print 1"),
+            Err("Expected 'LeftParen' token".to_string())
+        );
+        assert_eq!(parse("1;"), Err("Expected a factor".to_string()));
+        let e = parse("print(()); f()").unwrap();
+        assert_eq!(e.to_string(), "(; (print ()) (f ()))");
+        assert_eq!(sexp(&e.to_string()), Ok(e));
+    }
+
+    #[test]
+    fn parse_letcc() {
+        let cont = |param| Type::Cont { param: Box::new(param) };
+        assert_eq!(
+            parse("letcc k: cont int { 1 + k(2) }"),
+            Ok(Exp::LetCC {
+                var: "k".to_string(),
+                var_type: cont(Type::Int),
+                body: plus(
+                    int(1),
+                    Rc::new(Exp::App { fun: var("k"), arg: int(2) })
+                ),
+            })
+        );
+        assert_eq!(
+            parse("fn(k: cont (int -> cont str)) { k }"),
+            Ok(Exp::Lam {
+                var: "k".to_string(),
+                var_type: Some(cont(Type::Fun {
+                    param: Box::new(Type::Int),
+                    result: Box::new(cont(Type::Str)),
+                    effects: EffectRow::new(),
+                })),
+                body: var("k"),
+            })
+        );
+        assert_eq!(
+            parse("letcc k { 1 }"),
+            Err("Expected 'Colon' token".to_string())
+        );
+        assert_eq!(
+            parse("letcc k: cont { 1 }"),
+            Err("Expected a type".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_raise_and_try() {
+        let raise = |arg| Rc::new(Exp::Raise { arg });
+        // raise binds tighter than the operators, as application does
+        assert_eq!(
+            parse("raise x ++ y"),
+            Ok(Exp::Concat { left: raise(var("x")), right: var("y") })
+        );
+        assert_eq!(
+            parse("raise f(1)"),
+            Ok(Rc::unwrap_or_clone(raise(Rc::new(Exp::App {
+                fun: var("f"),
+                arg: int(1)
+            }))))
+        );
+        assert_eq!(
+            parse("try { 1; raise x } catch (e) { e }"),
+            Ok(Exp::Try {
+                body: Rc::new(Exp::Seq {
+                    first: int(1),
+                    second: raise(var("x"))
+                }),
+                var: "e".to_string(),
+                handler: var("e"),
+            })
+        );
+        assert_eq!(
+            parse("try { 1 } catch e { e }"),
+            Err("Expected 'LeftParen' token".to_string())
+        );
+        assert_eq!(
+            parse("try { 1 }"),
+            Err("Expected 'Catch' token".to_string())
+        );
+        assert_eq!(parse("raise"), Err("Expected a factor".to_string()));
+    }
+
+    #[test]
+    fn parse_effects_and_handlers() {
+        let ops = Ops::from([("Get".to_string(), (Type::Unit, Type::Int))]);
+        let parse = |input: &str| {
+            parse_expression_with(&tokenize(input)?, &Aliases::new(), &ops)
+        };
+        let get = |arg| Exp::Perform {
+            op: "Get".to_string(),
+            arg,
+            param: Box::new(Type::Unit),
+            result: Box::new(Type::Int),
+        };
+        assert_eq!(parse("perform Get(())"), Ok(get(Rc::new(Exp::Unit))));
+        assert_eq!(
+            parse(
+                "handle perform Get(()) with { Get(u, k) -> k(1); return x -> x }"
+            ),
+            Ok(Exp::Handle {
+                body: Rc::new(get(Rc::new(Exp::Unit))),
+                clauses: vec![Clause {
+                    op: "Get".to_string(),
+                    param: Type::Unit,
+                    result: Type::Int,
+                    var: "u".to_string(),
+                    k: "k".to_string(),
+                    body: Rc::new(Exp::App { fun: var("k"), arg: int(1) }),
+                }],
+                ret: Some(Return { var: "x".to_string(), body: var("x") }),
+            })
+        );
+        // an arrow may name the operations the function performs
+        let Ok(Exp::Lam { var_type: Some(var_type), .. }) =
+            parse("fn(f: (unit -{Get}-> int)) { f }")
+        else {
+            panic!("expected a function");
+        };
+        assert_eq!(var_type.to_string(), "(unit -{Get}-> int)");
+        assert_eq!(
+            parse("perform Put(1)"),
+            Err("Unknown effect: Put".to_string())
+        );
+        assert_eq!(
+            parse("fn(f: (unit -{Put}-> int)) { f }"),
+            Err("Unknown effect: Put".to_string())
+        );
+        // as well as io and effect variables
+        let Ok(Exp::Lam { var_type: Some(var_type), .. }) =
+            parse("fn(f: (unit -{io, e, Get}-> int)) { f }")
+        else {
+            panic!("expected a function");
+        };
+        assert_eq!(var_type.to_string(), "(unit -{Get, e, io}-> int)");
+        assert_eq!(
+            parse_effect_declaration(
+                &tokenize("effect put: int -> unit").unwrap(),
+                &Aliases::new(),
+                &ops
+            ),
+            Err("Effect names must be capitalized: put".to_string())
+        );
+        assert_eq!(
+            parse_effect_declaration(
+                &tokenize("effect Put: int -> unit").unwrap(),
+                &Aliases::new(),
+                &ops
+            ),
+            Ok(("Put".to_string(), (Type::Int, Type::Unit)))
+        );
+    }
+
+    #[test]
+    fn parse_ascriptions() {
+        let ascribe = |exp, typ| Exp::Ascribe { exp, typ };
+        assert_eq!(parse("(1 : int)"), Ok(ascribe(int(1), Type::Int)));
+        assert_eq!(
+            parse("(print(1); x : (int -> unit))"),
+            Ok(ascribe(
+                Rc::new(Exp::Seq {
+                    first: Rc::new(Exp::Print { arg: int(1) }),
+                    second: var("x"),
+                }),
+                Type::Fun {
+                    param: Box::new(Type::Int),
+                    result: Box::new(Type::Unit),
+                    effects: EffectRow::new(),
+                },
+            ))
+        );
+        assert_eq!(
+            parse("let x: int = 1 + 2 { x }"),
+            Ok(Exp::Let1 {
+                var: "x".to_string(),
+                value: Rc::new(ascribe(plus(int(1), int(2)), Type::Int)),
+                body: var("x"),
+            })
+        );
+        assert_eq!(parse("(x : dyn)"), Ok(ascribe(var("x"), Type::Dyn)));
+        assert_eq!(parse("(x : top)"), Ok(ascribe(var("x"), Type::Top)));
+        assert_eq!(parse("(1 : )"), Err("Expected a type".to_string()));
+        assert_eq!(
+            parse("let x: = 1 { x }"),
+            Err("Expected a type".to_string())
+        );
+        assert_eq!(
+            parse("let x int = 1 { x }"),
+            Err("Expected 'Equal' token".to_string())
+        );
+        let e = parse("let x: str = (y : str) { x }").unwrap();
+        assert_eq!(e.to_string(), "(let x (: (: y str) str) x)");
+        assert_eq!(sexp(&e.to_string()), Ok(e));
+    }
+
+    #[test]
+    fn parse_type_aliases() {
+        let mut aliases = Aliases::new();
+        let ops = Ops::new();
+        let define = |src: &str, aliases: &Aliases| {
+            parse_type_definition(&tokenize(src).unwrap(), aliases, &ops)
+        };
+        let fun = |param, result| Type::Fun {
+            param: Box::new(param),
+            result: Box::new(result),
+            effects: EffectRow::new(),
+        };
+        let (name, typ) = define("type F = (int -> int)", &aliases).unwrap();
+        assert_eq!((name.as_str(), &typ), ("F", &fun(Type::Int, Type::Int)));
+        aliases.insert(name, typ);
+        let (name, typ) = define("type G = (F -> F)", &aliases).unwrap();
+        assert_eq!(typ.to_string(), "(F -> F)");
+        aliases.insert(name, typ);
+
+        let e = parse_expression_with(
+            &tokenize("fn(g: G) { g }").unwrap(),
+            &aliases,
+            &ops,
+        )
+        .unwrap();
+        let Exp::Lam { var_type: Some(var_type), .. } = &e else {
+            panic!("{e}")
+        };
+        assert_eq!(var_type.to_string(), "G");
+        // an alias equals its expansion
+        let f = fun(Type::Int, Type::Int);
+        assert_eq!(*var_type, fun(f.clone(), f));
+        assert_ne!(*var_type, fun(Type::Int, Type::Int));
+        assert_eq!(
+            parse_sexp_with(&tokenize(&e.to_string()).unwrap(), &aliases, &ops),
+            Ok(e)
+        );
+
+        assert_eq!(parse("fn(x: G) { x }"), Err("Unknown type: G".to_string()));
+        assert_eq!(
+            define("type H = H", &aliases),
+            Err("Unknown type: H".to_string())
+        );
+        assert_eq!(
+            define("type int = str", &aliases),
+            Err("Expected an identifier".to_string())
+        );
+        assert_eq!(
+            define("type H = int int", &aliases),
+            Err("Expected to find end of input".to_string())
+        );
+        assert_eq!(parse("type"), Err("Expected a factor".to_string()));
+    }
+
+    #[test]
+    fn parse_application() {
+        assert_eq!(
+            parse("f(1)(x + 2)"),
+            Ok(Exp::App {
+                fun: Rc::new(Exp::App { fun: var("f"), arg: int(1) }),
+                arg: plus(var("x"), int(2)),
+            })
+        );
+        // applications bind tighter than binary operators
+        assert_eq!(
+            parse("f(1) + 2"),
+            Ok(Rc::unwrap_or_clone(plus(
+                Rc::new(Exp::App { fun: var("f"), arg: int(1) }),
+                int(2)
+            )))
+        );
+        // a lambda is a factor, so it can be applied directly
+        assert_eq!(
+            parse("fn(a: int) { a }(3)"),
+            Ok(Exp::App {
+                fun: Rc::new(Exp::Lam {
+                    var: "a".to_string(),
+                    var_type: Some(Type::Int),
+                    body: var("a"),
+                }),
+                arg: int(3),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse(""), Err("Expected a factor".to_string()));
+        assert_eq!(
+            parse("1 2"),
+            Err("Expected to find end of input".to_string())
+        );
+        assert_eq!(parse("(1"), Err("Expected 'RightParen' token".to_string()));
+        assert_eq!(parse("1 +"), Err("Expected a factor".to_string()));
+        assert_eq!(
+            parse("if true { 1 }"),
+            Err("Expected 'Else' token".to_string())
+        );
+        assert_eq!(
+            parse("let 1 = 2 { 3 }"),
+            Err("Expected an identifier".to_string())
+        );
+        assert_eq!(
+            parse("letcc k { k }"),
+            Err("Expected 'Colon' token".to_string())
+        );
+        assert_eq!(
+            parse("fn(x: num) { x }"),
+            Err("Unknown type: num".to_string())
+        );
+        assert_eq!(parse("fn(x: 1) { x }"), Err("Expected a type".to_string()));
+        assert_eq!(
+            parse("fn(x: (int)) { x }"),
+            Err("Expected 'RightArrow' token".to_string())
+        );
+    }
+
+    fn sexp(input: &str) -> Result<Exp, String> {
+        parse_sexp(&tokenize(input)?)
+    }
+
+    #[test]
+    fn sexp_atoms_and_operators() {
+        assert_eq!(sexp("-3"), Ok(Exp::Int(-3)));
+        assert_eq!(sexp("true"), Ok(Exp::Bool(true)));
+        assert_eq!(sexp("\"s\""), Ok(Exp::Str("s".to_string())));
+        assert_eq!(sexp("x"), Ok(Exp::Var("x".to_string())));
+        assert_eq!(sexp("(+ (+ 1 2) 3)"), parse("1 + 2 + 3"));
+        assert_eq!(sexp("(++ a (++ b c))"), parse("a ++ (b ++ c)"));
+        assert_eq!(sexp("(< 1 2)"), parse("1 < 2"));
+    }
+
+    #[test]
+    fn sexp_binding_forms() {
+        assert_eq!(sexp("(if b 1 2)"), parse("if b { 1 } else { 2 }"));
+        assert_eq!(sexp("(let x 1 (+ x x))"), parse("let x = 1 { x + x }"));
+        assert_eq!(
+            sexp("(fn (f: (int -> bool)) (f 1))"),
+            parse("fn(f: (int -> bool)) { f(1) }")
+        );
+        assert_eq!(sexp("((adder 2) 3)"), parse("adder(2)(3)"));
+        assert_eq!(sexp("(adder 2 3)"), parse("adder(2, 3)"));
+        assert_eq!(
+            sexp("(fn (x: int) (y: int) (+ x y))"),
+            parse("fn(x: int, y: int) { x + y }")
+        );
+        assert_eq!(
+            sexp("(fn (x: int) (y: int))"),
+            Err("Expected an s-expression".to_string())
+        );
+        assert_eq!(
+            sexp("(letcc (k: cont int) (+ 1 (k 2)))"),
+            parse("letcc k: cont int { 1 + k(2) }")
+        );
+        assert_eq!(
+            sexp("(try (raise \"x\") e (++ e e))"),
+            parse("try { raise \"x\" } catch (e) { e ++ e }")
+        );
+    }
+
+    #[test]
+    fn display_escapes_strings() {
+        let e = Exp::Str("say \"hi\"\n\t\\ \u{7}".to_string());
+        assert_eq!(e.to_string(), r#""say \"hi\"\n\t\\ \u{7}""#);
+        assert_eq!(parse(&e.to_string()), Ok(e.clone()));
+        assert_eq!(sexp(&e.to_string()), Ok(e));
+    }
+
+    #[test]
+    fn parse_floats() {
+        assert_eq!(
+            parse("1.5 + -2e3"),
+            Ok(Exp::Plus {
+                left: Rc::new(Exp::Float(1.5)),
+                right: Rc::new(Exp::Float(-2000.0)),
+            })
+        );
+        assert_eq!(
+            parse("fn(x: float) { x }"),
+            Ok(Exp::Lam {
+                var: "x".to_string(),
+                var_type: Some(Type::Float),
+                body: var("x"),
+            })
+        );
+        assert_eq!(
+            parse("float->int(int->float(1))"),
+            Ok(Exp::App {
+                fun: var("float->int"),
+                arg: Rc::new(Exp::App { fun: var("int->float"), arg: int(1) }),
+            })
+        );
+        let e = parse("1e-7 < 2.0").unwrap();
+        assert_eq!(e.to_string(), "(< 1e-7 2.0)");
+        assert_eq!(sexp(&e.to_string()), Ok(e));
+    }
+
+    #[test]
+    fn parse_conversions() {
+        assert_eq!(
+            parse("int->str(1)"),
+            Ok(Exp::App { fun: var("int->str"), arg: int(1) })
+        );
+        assert_eq!(sexp("(str->int \"1\")"), parse("str->int(\"1\")"));
+        assert_eq!(parse("int->int"), Err("Expected a factor".to_string()));
+        assert_eq!(sexp("str"), Err("Expected an s-expression".to_string()));
+    }
+
+    #[test]
+    fn sexp_reads_display_output() {
+        let e = parse(
+            "let adder = fn(x:int) {fn(y:int) {x+y}} {adder(2)(3) + if 1 < 2 { 1 } else { 2 }} ++ \"s\"",
+        )
+        .unwrap();
+        assert_eq!(sexp(&e.to_string()), Ok(e));
+    }
+
+    #[test]
+    fn sexp_errors() {
+        assert_eq!(sexp("(f)"), Err("Expected an s-expression".to_string()));
+        assert_eq!(sexp("(+ 1)"), Err("Expected an s-expression".to_string()));
+        assert_eq!(
+            sexp("(+ 1 2 3)"),
+            Err("Expected 'RightParen' token".to_string())
+        );
+        assert_eq!(
+            sexp("1 2"),
+            Err("Expected to find end of input".to_string())
+        );
+        assert_eq!(
+            sexp("(let 1 2 3)"),
+            Err("Expected an identifier".to_string())
+        );
+        assert_eq!(
+            sexp(&format!("{}1{}", "(".repeat(1000), ")".repeat(1000))),
+            Err("Expression nested too deeply".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_rejects_deep_nesting() {
+        let nested = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse(&nested(MAX_NESTING - 1)).is_ok());
+        assert_eq!(
+            parse(&nested(100_000)),
+            Err("Expression nested too deeply".to_string())
+        );
+        let chain = |n| vec!["1"; n].join(" + ");
+        assert!(parse(&chain(MAX_NESTING - 1)).is_ok());
+        assert_eq!(
+            parse(&chain(100_000)),
+            Err("Expression nested too deeply".to_string())
+        );
+        let calls = |n| format!("f{}", "(1)".repeat(n));
+        assert_eq!(
+            parse(&calls(100_000)),
+            Err("Expression nested too deeply".to_string())
+        );
+        let types =
+            |n| format!("fn(x: {}int{}) {{ x }}", "(".repeat(n), ")".repeat(n));
+        assert_eq!(
+            parse(&types(100_000)),
+            Err("Expression nested too deeply".to_string())
+        );
+    }
+}
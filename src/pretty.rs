@@ -0,0 +1,442 @@
+use super::*;
+
+// Prints expressions back in the brace/infix syntax accepted by
+// parse_expression, unlike Exp's Display which prints s-expressions.
+//
+// An expression that fits in the remaining width is printed on one line;
+// otherwise the bodies of let, if and fn are moved onto their own lines,
+// indented by `indent` spaces per level, with each part of a sequence
+// on a line of its own.
+pub struct Printer {
+    pub indent: usize,
+    pub width: usize,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Printer { indent: 4, width: 80 }
+    }
+}
+
+// Pretty print with the default settings.
+pub fn pretty(e: &Exp) -> String {
+    Printer::default().print(e)
+}
+
+impl Printer {
+    pub fn print(&self, e: &Exp) -> String {
+        // a line that leaves nothing open ends a REPL entry, so a sequence
+        // broken across lines at the top is kept in parentheses
+        match self.body(e, 0) {
+            body if body.contains('\n')
+                && matches!(e.bare(), Exp::Seq { .. }) =>
+            {
+                format!("({body})")
+            }
+            body => body,
+        }
+    }
+
+    // Print `e` at nesting `level`, breaking it across lines if it doesn't
+    // fit on the rest of the line.
+    fn layout(&self, e: &Exp, level: usize) -> String {
+        let flat = flat(e);
+        if self.indent * level + flat.len() <= self.width {
+            return flat;
+        }
+        match e.bare() {
+            Exp::Plus { left, right } => self.binary(left, "+", right, level),
+            Exp::Concat { left, right } => {
+                self.binary(left, "++", right, level)
+            }
+            Exp::LessThan { left, right } => {
+                self.binary(left, "<", right, level)
+            }
+            Exp::Cnd { tst, thn, els } => format!(
+                "if {} {} else {}",
+                self.layout(tst, level),
+                self.block(thn, level),
+                self.block(els, level)
+            ),
+            Exp::Let1 { var, value, body } => match value.bare() {
+                Exp::Ascribe { exp, typ } => format!(
+                    "let {var}: {typ} = {} {}",
+                    self.layout(exp, level),
+                    self.block(body, level)
+                ),
+                _ => format!(
+                    "let {var} = {} {}",
+                    self.layout(value, level),
+                    self.block(body, level)
+                ),
+            },
+            Exp::Lam { var, var_type, body } => {
+                format!(
+                    "fn({}) {}",
+                    param(var, var_type),
+                    self.block(body, level)
+                )
+            }
+            Exp::LetCC { var, var_type, body } => {
+                format!("letcc {var}: {var_type} {}", self.block(body, level))
+            }
+            Exp::App { fun, arg } if *arg.bare() != Exp::Unit => format!(
+                "{}({})",
+                self.callee(fun, level),
+                self.layout(arg, level)
+            ),
+            Exp::Print { arg } => format!("print({})", self.layout(arg, level)),
+            Exp::Raise { arg } => format!("raise {}", self.operand(arg, level)),
+            Exp::Try { body, var, handler } => format!(
+                "try {} catch ({var}) {}",
+                self.block(body, level),
+                self.block(handler, level)
+            ),
+            Exp::Perform { op, arg, .. } => {
+                format!("perform {op}({})", self.layout(arg, level))
+            }
+            Exp::Handle { body, clauses, ret } => {
+                let inner = " ".repeat(self.indent * (level + 1));
+                let outer = " ".repeat(self.indent * level);
+                let handlers: Vec<String> = handlers(clauses, ret)
+                    .map(|(head, e)| {
+                        format!("{head} -> {}", self.layout(e, level + 1))
+                    })
+                    .collect();
+                format!(
+                    "handle {} with {{\n{inner}{}\n{outer}}}",
+                    self.layout(body, level),
+                    handlers.join(&format!(";\n{inner}"))
+                )
+            }
+            Exp::Seq { .. } => format!("({})", self.body(e, level)),
+            _ => flat,
+        }
+    }
+
+    // Print `e` where a sequence needs no parentheses: at the top or as
+    // the body of a block.
+    fn body(&self, e: &Exp, level: usize) -> String {
+        if !matches!(e.bare(), Exp::Seq { .. }) {
+            return self.layout(e, level);
+        }
+        let flat = flat_body(e);
+        if self.indent * level + flat.len() <= self.width {
+            return flat;
+        }
+        let mut parts = Vec::new();
+        let mut rest = e;
+        while let Exp::Seq { first, second } = rest.bare() {
+            parts.push(self.layout(first, level));
+            rest = second;
+        }
+        parts.push(self.layout(rest, level));
+        parts.join(&format!(";\n{}", " ".repeat(self.indent * level)))
+    }
+
+    fn binary(
+        &self,
+        left: &Exp,
+        op: &str,
+        right: &Exp,
+        level: usize,
+    ) -> String {
+        format!(
+            "{} {op} {}",
+            self.layout(left, level),
+            self.operand(right, level)
+        )
+    }
+
+    // Print a term that may not be a binary operation without parentheses:
+    // the right operand of an operator or the argument of a raise.
+    fn operand(&self, e: &Exp, level: usize) -> String {
+        if is_binary(e) {
+            format!("({})", self.layout(e, level))
+        } else {
+            self.layout(e, level)
+        }
+    }
+
+    // Print a function being applied, which a raise would take as its
+    // argument's.
+    fn callee(&self, e: &Exp, level: usize) -> String {
+        if is_binary(e) || matches!(e.bare(), Exp::Raise { .. }) {
+            format!("({})", self.layout(e, level))
+        } else {
+            self.layout(e, level)
+        }
+    }
+
+    // A { body } on its own indented lines.
+    fn block(&self, e: &Exp, level: usize) -> String {
+        let inner = " ".repeat(self.indent * (level + 1));
+        let outer = " ".repeat(self.indent * level);
+        format!("{{\n{inner}{}\n{outer}}}", self.body(e, level + 1))
+    }
+}
+
+// The heads of a handler's clauses, as in Op(x, k) or return x, each with
+// its body.
+fn handlers<'a>(
+    clauses: &'a [Clause],
+    ret: &'a Option<Return>,
+) -> impl Iterator<Item = (String, &'a Exp)> {
+    let ops = clauses.iter().map(|Clause { op, var, k, body, .. }| {
+        (format!("{op}({var}, {k})"), &**body)
+    });
+    let ret = ret
+        .iter()
+        .map(|Return { var, body }| (format!("return {var}"), &**body));
+    ops.chain(ret)
+}
+
+// A fn's parameter, with its type if it was given one.
+fn param(var: &str, var_type: &Option<Type>) -> String {
+    match var_type {
+        Some(var_type) => format!("{var}: {var_type}"),
+        None => var.to_string(),
+    }
+}
+
+fn is_binary(e: &Exp) -> bool {
+    matches!(
+        e.bare(),
+        Exp::Plus { .. } | Exp::Concat { .. } | Exp::LessThan { .. }
+    )
+}
+
+// The single-line form of `e`.
+pub fn flat(e: &Exp) -> String {
+    let operand = |e: &Exp| {
+        if is_binary(e) { format!("({})", flat(e)) } else { flat(e) }
+    };
+    let callee = |e: &Exp| match e.bare() {
+        Exp::Raise { .. } => format!("({})", flat(e)),
+        _ => operand(e),
+    };
+    match e {
+        Exp::Int(n) => format!("{n}"),
+        Exp::Float(x) => format!("{x:?}"),
+        Exp::Bool(b) => format!("{b}"),
+        Exp::Str(s) => Quoted(s).to_string(),
+        Exp::Unit => "()".to_string(),
+        Exp::Var(v) => v.clone(),
+        Exp::Plus { left, right } => {
+            format!("{} + {}", flat(left), operand(right))
+        }
+        Exp::Concat { left, right } => {
+            format!("{} ++ {}", flat(left), operand(right))
+        }
+        Exp::LessThan { left, right } => {
+            format!("{} < {}", flat(left), operand(right))
+        }
+        Exp::Cnd { tst, thn, els } => format!(
+            "if {} {{ {} }} else {{ {} }}",
+            flat(tst),
+            flat_body(thn),
+            flat_body(els)
+        ),
+        Exp::Let1 { var, value, body } => match value.bare() {
+            Exp::Ascribe { exp, typ } => format!(
+                "let {var}: {typ} = {} {{ {} }}",
+                flat(exp),
+                flat_body(body)
+            ),
+            _ => {
+                format!("let {var} = {} {{ {} }}", flat(value), flat_body(body))
+            }
+        },
+        Exp::Lam { var, var_type, body } => {
+            format!("fn({}) {{ {} }}", param(var, var_type), flat_body(body))
+        }
+        Exp::App { fun, arg } if *arg.bare() == Exp::Unit => {
+            format!("{}()", callee(fun))
+        }
+        Exp::App { fun, arg } => format!("{}({})", callee(fun), flat(arg)),
+        Exp::Seq { .. } => format!("({})", flat_body(e)),
+        Exp::Print { arg } => format!("print({})", flat(arg)),
+        Exp::Ascribe { exp, typ } => format!("({} : {typ})", flat_body(exp)),
+        // a cast reads back as the ascription that checks the same
+        Exp::Cast { exp, to, .. } => format!("({} : {to})", flat_body(exp)),
+        Exp::At { exp, .. } => flat(exp),
+        Exp::LetCC { var, var_type, body } => {
+            format!("letcc {var}: {var_type} {{ {} }}", flat_body(body))
+        }
+        Exp::Raise { arg } => format!("raise {}", operand(arg)),
+        Exp::Try { body, var, handler } => format!(
+            "try {{ {} }} catch ({var}) {{ {} }}",
+            flat_body(body),
+            flat_body(handler)
+        ),
+        Exp::Perform { op, arg, .. } => format!("perform {op}({})", flat(arg)),
+        Exp::Handle { body, clauses, ret } => {
+            let handlers: Vec<String> = handlers(clauses, ret)
+                .map(|(head, e)| format!("{head} -> {}", flat(e)))
+                .collect();
+            format!("handle {} with {{ {} }}", flat(body), handlers.join("; "))
+        }
+    }
+}
+
+// The single-line form of `e` where a sequence needs no parentheses.
+fn flat_body(e: &Exp) -> String {
+    match e.bare() {
+        Exp::Seq { first, second } => {
+            format!("{}; {}", flat(first), flat_body(second))
+        }
+        _ => flat(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{
+        parse_expression, parse_expression_at, parse_expression_with, tokenize,
+        tokenize_at,
+    };
+
+    fn parse(input: &str) -> Exp {
+        parse_expression(&tokenize(input).unwrap()).unwrap()
+    }
+
+    fn narrow(indent: usize) -> Printer {
+        Printer { indent, width: 0 }
+    }
+
+    #[test]
+    fn flat_uses_minimal_parentheses() {
+        let cases = [
+            ("1 + 2 + 3", "1 + 2 + 3"),
+            ("1 + (2 + 3)", "1 + (2 + 3)"),
+            ("((1 < 2))", "1 < 2"),
+            ("(f)(1)(2)", "f(1)(2)"),
+            ("(f + g)(x + 1)", "(f + g)(x + 1)"),
+            ("(fn(a:int){a+a})(13)", "fn(a: int) { a + a }(13)"),
+            ("let x=1{x}+ -2", "let x = 1 { x } + -2"),
+            (
+                "if a<b {\"s\"} else {\"t\"++u}",
+                "if a < b { \"s\" } else { \"t\" ++ u }",
+            ),
+            (
+                "fn(f:(int->(int->bool))){f}",
+                "fn(f: (int -> (int -> bool))) { f }",
+            ),
+            ("print(1);(print(2));f( )", "print(1); print(2); f()"),
+            ("(a; b); c", "(a; b); c"),
+            ("f((a; b)) + (())", "f((a; b)) + ()"),
+            ("let x = (a; b) { a; b }", "let x = (a; b) { a; b }"),
+            ("(1+2:int) + (a;b:str)", "(1 + 2 : int) + (a; b : str)"),
+            ("let x = (1:int) {x}", "let x: int = 1 { x }"),
+            ("let x:int = (1:int) {x}", "let x: int = (1 : int) { x }"),
+            (
+                "letcc k : cont (int->int) {k(f)}",
+                "letcc k: cont (int -> int) { k(f) }",
+            ),
+            ("raise (x ++ y) ++ z", "raise (x ++ y) ++ z"),
+            ("(raise f)(x) + raise f(x)", "(raise f)(x) + raise f(x)"),
+            ("try{a;raise b}catch(e){e}", "try { a; raise b } catch (e) { e }"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(pretty(&parse(input)), expected);
+        }
+    }
+
+    #[test]
+    fn long_expressions_break_bodies_onto_indented_lines() {
+        let e = parse(
+            "let add = fn(a: int) { fn(b: int) { a + b } } { add(3)(4) }",
+        );
+        assert_eq!(
+            narrow(4).print(&e),
+            "let add = fn(a: int) {\n    fn(b: int) {\n        a + b\n    }\n} {\n    add(3)(4)\n}"
+        );
+        assert_eq!(
+            narrow(2).print(&parse("if b { 1 } else { 2 }")),
+            "if b {\n  1\n} else {\n  2\n}"
+        );
+        assert_eq!(
+            narrow(4).print(&parse("fn(x: str) { x ++ \"!\" }(\"hello\")")),
+            "fn(x: str) {\n    x ++ \"!\"\n}(\"hello\")"
+        );
+    }
+
+    #[test]
+    fn long_sequences_put_each_part_on_its_own_line() {
+        let e = parse("print(\"one\"); print(\"two\"); 3");
+        assert_eq!(
+            Printer { indent: 4, width: 20 }.print(&e),
+            "(print(\"one\");\nprint(\"two\");\n3)"
+        );
+        let e = parse("fn(x: int) { print(x); print(x + 1); x }");
+        assert_eq!(
+            Printer { indent: 2, width: 20 }.print(&e),
+            "fn(x: int) {\n  print(x);\n  print(x + 1);\n  x\n}"
+        );
+    }
+
+    #[test]
+    fn only_what_does_not_fit_is_broken() {
+        let e = parse("let s = \"hi\" { s ++ \" there\" }");
+        let printer = Printer { indent: 4, width: 20 };
+        assert_eq!(
+            printer.print(&e),
+            "let s = \"hi\" {\n    s ++ \" there\"\n}"
+        );
+    }
+
+    #[test]
+    fn handlers_put_each_clause_on_its_own_line() {
+        let ops = Ops::from([("Get".to_string(), (Type::Unit, Type::Int))]);
+        let tokens = tokenize(
+            "handle perform Get(())+1 with {Get(u,k)->k(1);return x->x<2}",
+        )
+        .unwrap();
+        let e = parse_expression_with(&tokens, &Aliases::new(), &ops).unwrap();
+        assert_eq!(
+            pretty(&e),
+            "handle perform Get(()) + 1 with \
+             { Get(u, k) -> k(1); return x -> x < 2 }"
+        );
+        assert_eq!(
+            narrow(2).print(&e),
+            "handle perform Get(()) + 1 with {\n  Get(u, k) -> k(1);\n  return x -> x < 2\n}"
+        );
+    }
+
+    #[test]
+    fn positions_do_not_change_the_output() {
+        let input = "let x = (1 + 2 : int) { f(); (raise x)(x) + (x ++ y) }";
+        let (tokens, positions) = tokenize_at(input, 1).unwrap();
+        let e = parse_expression_at(
+            &tokens,
+            &positions,
+            &Aliases::new(),
+            &Ops::new(),
+        )
+        .unwrap();
+        assert!(matches!(e, Exp::At { .. }));
+        for printer in [Printer::default(), narrow(0), narrow(3)] {
+            assert_eq!(printer.print(&e), printer.print(&parse(input)));
+        }
+    }
+
+    #[test]
+    fn printed_expressions_parse_back() {
+        let inputs = [
+            "let a = 123 { let a = 9 { a + -2 } + let a = 4 { a + 2 } } + -13",
+            "let x = 3 { fn(y:int) { x + y } }(4)",
+            "fn(f: (int -> int)) { f(10) }(fn(x: int) { x + 2 })",
+            "fn(f: (int -> int)) { f(10) }(fn(x) { x + 2 })",
+            "(1 + 2)(3 < 4) ++ (a ++ b)",
+            "let f = fn(u: unit) { print(\"called\"); 1 } { f(); (f(); f()) + f(()) }",
+        ];
+        for input in inputs {
+            let e = parse(input);
+            for printer in [Printer::default(), narrow(0), narrow(3)] {
+                assert_eq!(parse(&printer.print(&e)), e, "{input}");
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use super::*;
+
+// Built-in functions. They are curried like user functions, so a
+// primitive value collects its arguments one application at a time and
+// runs once it has them all.
+
+fn fun(param: Type, result: Type) -> Type {
+    Type::Fun {
+        param: Box::new(param),
+        result: Box::new(result),
+        effects: EffectRow::new(),
+    }
+}
+
+// The type of the primitive called `name`, if there is one.
+pub fn prim_type(name: &str) -> Option<Type> {
+    match name {
+        "length" => Some(fun(Type::Str, Type::Int)),
+        "substring" => {
+            Some(fun(Type::Str, fun(Type::Int, fun(Type::Int, Type::Str))))
+        }
+        "str-eq" => Some(fun(Type::Str, fun(Type::Str, Type::Bool))),
+        "int->str" => Some(fun(Type::Int, Type::Str)),
+        "str->int" => Some(fun(Type::Str, Type::IntOption)),
+        "is-int" => Some(fun(Type::IntOption, Type::Bool)),
+        "int-or" => Some(fun(Type::IntOption, fun(Type::Int, Type::Int))),
+        "int->float" => Some(fun(Type::Int, Type::Float)),
+        "float->int" => Some(fun(Type::Float, Type::Int)),
+        _ => None,
+    }
+}
+
+// The number of arguments a primitive takes, read off its type.
+pub fn arity(name: &str) -> usize {
+    let mut t = prim_type(name);
+    let mut n = 0;
+    while let Some(Type::Fun { result, .. }) = t {
+        n += 1;
+        t = Some(*result);
+    }
+    n
+}
+
+// Apply a primitive that has already been given `args` to one more.
+pub fn apply(name: &str, args: &[Value], arg: Value) -> Result<Value, String> {
+    let mut args = args.to_vec();
+    args.push(arg);
+    if args.len() < arity(name) {
+        return Ok(Value::Prim { name: name.to_string(), args });
+    }
+
+    match (name, args.as_slice()) {
+        ("length", [Value::Str(s)]) => {
+            Ok(Value::Int(s.chars().count() as isize))
+        }
+        ("substring", [Value::Str(s), Value::Int(start), Value::Int(end)]) => {
+            let len = s.chars().count() as isize;
+            if 0 <= *start && start <= end && *end <= len {
+                let (start, end) = (*start as usize, *end as usize);
+                Ok(Value::Str(
+                    s.chars().skip(start).take(end - start).collect(),
+                ))
+            } else {
+                Err(format!(
+                    "substring {start} {end} out of range for a string of length {len}"
+                ))
+            }
+        }
+        ("str-eq", [Value::Str(l), Value::Str(r)]) => Ok(Value::Bool(l == r)),
+        ("int->str", [Value::Int(n)]) => Ok(Value::Str(n.to_string())),
+        // checked rather than raising, so that a caller can branch on
+        // whether there was an integer; a sign is only ever a minus
+        ("str->int", [Value::Str(s)]) => {
+            Ok(Value::IntOption(s.parse().ok().filter(|_| !s.starts_with('+'))))
+        }
+        ("is-int", [Value::IntOption(n)]) => Ok(Value::Bool(n.is_some())),
+        ("int-or", [Value::IntOption(n), Value::Int(default)]) => {
+            Ok(Value::Int(n.unwrap_or(*default)))
+        }
+        ("int->float", [Value::Int(n)]) => Ok(Value::Float(*n as f64)),
+        // rounds toward zero; the float has to land in the int range
+        ("float->int", [Value::Float(x)]) => {
+            let n = x.trunc();
+            if n >= isize::MIN as f64 && n < isize::MAX as f64 {
+                Ok(Value::Int(n as isize))
+            } else {
+                Err(format!("float->int: {x:?} is out of range"))
+            }
+        }
+        (_, args) => Err(format!("{name} can't be applied to {args:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn str(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+        let mut f = Value::Prim { name: name.to_string(), args: vec![] };
+        for arg in args {
+            let Value::Prim { name, args } = f else {
+                panic!("{name} applied to too many arguments");
+            };
+            f = apply(&name, &args, arg)?;
+        }
+        Ok(f)
+    }
+
+    #[test]
+    fn string_primitives() {
+        assert_eq!(call("length", vec![str("héllo")]), Ok(Value::Int(5)));
+        assert_eq!(
+            call("substring", vec![str("héllo"), Value::Int(1), Value::Int(3)]),
+            Ok(str("él"))
+        );
+        assert_eq!(
+            call("substring", vec![str("abc"), Value::Int(3), Value::Int(3)]),
+            Ok(str(""))
+        );
+        assert_eq!(
+            call("str-eq", vec![str("a"), str("a")]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            call("str-eq", vec![str("a"), str("b")]),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(call("int->str", vec![Value::Int(-12)]), Ok(str("-12")));
+    }
+
+    #[test]
+    fn str_to_int_is_checked() {
+        let some = |n| Ok(Value::IntOption(Some(n)));
+        assert_eq!(call("str->int", vec![str("-12")]), some(-12));
+        assert_eq!(
+            call("str->int", vec![str("12a")]),
+            Ok(Value::IntOption(None))
+        );
+        assert_eq!(
+            call("str->int", vec![str("+1")]),
+            Ok(Value::IntOption(None))
+        );
+        assert_eq!(call("str->int", vec![str("")]), Ok(Value::IntOption(None)));
+        assert_eq!(
+            call("is-int", vec![Value::IntOption(Some(0))]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            call("is-int", vec![Value::IntOption(None)]),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            call("int-or", vec![Value::IntOption(Some(3)), Value::Int(0)]),
+            Ok(Value::Int(3))
+        );
+        assert_eq!(
+            call("int-or", vec![Value::IntOption(None), Value::Int(0)]),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(Value::IntOption(Some(3)).to_string(), "some(3)");
+        assert_eq!(Value::IntOption(None).to_string(), "none");
+    }
+
+    #[test]
+    fn partial_application_collects_arguments() {
+        let partial = call("substring", vec![str("abc"), Value::Int(1)]);
+        assert_eq!(
+            partial,
+            Ok(Value::Prim {
+                name: "substring".to_string(),
+                args: vec![str("abc"), Value::Int(1)],
+            })
+        );
+        assert_eq!(
+            partial.unwrap().to_string(),
+            "primitive(substring \"abc\" 1)"
+        );
+    }
+
+    #[test]
+    fn primitive_errors() {
+        assert_eq!(
+            call("substring", vec![str("abc"), Value::Int(2), Value::Int(4)]),
+            Err("substring 2 4 out of range for a string of length 3"
+                .to_string())
+        );
+        assert_eq!(
+            call("substring", vec![str("abc"), Value::Int(2), Value::Int(1)]),
+            Err("substring 2 1 out of range for a string of length 3"
+                .to_string())
+        );
+        assert_eq!(
+            call("length", vec![Value::Int(1)]),
+            Err("length can't be applied to [Int(1)]".to_string())
+        );
+    }
+
+    #[test]
+    fn primitive_types() {
+        assert_eq!(prim_type("length").unwrap().to_string(), "(str -> int)");
+        assert_eq!(
+            prim_type("substring").unwrap().to_string(),
+            "(str -> (int -> (int -> str)))"
+        );
+        assert_eq!(prim_type("str->int").unwrap().to_string(), "(str -> int?)");
+        assert_eq!(prim_type("nope"), None);
+        assert_eq!(arity("substring"), 3);
+        assert_eq!(arity("int->str"), 1);
+    }
+}
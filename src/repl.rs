@@ -0,0 +1,450 @@
+use super::*;
+use interp::Strategy;
+use interp::elaborate;
+use interp::interp_with;
+use interp::with_output;
+use parse::UNTERMINATED_COMMENT;
+use parse::parse_effect_declaration;
+use parse::parse_expression_at;
+use parse::parse_sexp_at;
+use parse::parse_type_definition;
+use parse::tokenize_at;
+use std::io::{self, BufRead, Write};
+use step::step;
+
+// Which reader turns the tokens of an entry into an expression.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Syntax {
+    // the brace/infix language, e.g. let x = 1 { x + 2 }
+    #[default]
+    Surface,
+    // the form the REPL prints on its ast line, e.g. (let x 1 (+ x 2))
+    Sexp,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Config {
+    pub syntax: Syntax,
+    // how arguments and let values are bound when evaluating
+    pub strategy: Strategy,
+    // evaluate with the CPS interpreter instead of `interp_with`, as is
+    // done anyway for an entry using letcc or perform; it only runs
+    // call-by-value
+    pub cps: bool,
+    // show each step of a small-step reduction before the result; the
+    // reducer is call-by-value, so the steps are whatever the strategy
+    pub trace: bool,
+}
+
+// Scan a token list and count its total nesting level. An entry is
+// complete once this is zero at the end of a line.
+pub fn nesting(tokens: &[Token]) -> isize {
+    let mut count = 0;
+    for elt in tokens {
+        match elt {
+            Token::LeftParen => count += 1,
+            Token::RightParen => count -= 1,
+            Token::LeftBrace | Token::LeftEffect => count += 1,
+            Token::RightBrace => count -= 1,
+            _ => {}
+        }
+    }
+    count
+}
+
+// Run the read-eval-print loop with the default configuration.
+pub fn run<R: BufRead, W: Write>(input: R, out: &mut W) -> io::Result<()> {
+    run_with(&Config::default(), input, out)
+}
+
+// Run the read-eval-print loop until `input` is exhausted, writing
+// everything the user would see to `out`.
+pub fn run_with<R: BufRead, W: Write>(
+    config: &Config,
+    mut input: R,
+    out: &mut W,
+) -> io::Result<()> {
+    let empty_nv = Env::new();
+    let empty_tnv = TEnv::new();
+    // the type aliases and effect operations declared so far
+    let mut aliases = Aliases::new();
+    let mut ops = Ops::new();
+    // how many lines have been read, for the positions casts give
+    let mut lines = 0;
+
+    loop {
+        // print a prompt
+        writeln!(out, "\nPlease enter an expression:")?;
+        let mut tokens = Vec::new();
+        let mut positions = Vec::new();
+        // the lines of a block comment still open at the end of a line,
+        // and the line it starts on
+        let mut open_comment = String::new();
+        let mut comment_line = 0;
+
+        loop {
+            // read a line of input, quit on ctrl-d and skip empty lines
+            // outside comments
+            let mut line = String::new();
+            let len = input.read_line(&mut line)?;
+            if len == 0 {
+                return Ok(());
+            }
+            lines += 1;
+            if line.trim().is_empty() && open_comment.is_empty() {
+                continue;
+            }
+
+            // tokenize, continuing any block comment from earlier lines
+            let text = format!("{open_comment}{}", line.trim_end());
+            let first =
+                if open_comment.is_empty() { lines } else { comment_line };
+            open_comment.clear();
+            match tokenize_at(&text, first) {
+                Ok((new_tokens, new_positions)) => {
+                    tokens.extend(new_tokens);
+                    positions.extend(new_positions);
+                }
+                Err(msg) if msg == UNTERMINATED_COMMENT => {
+                    open_comment = text + "\n";
+                    comment_line = first;
+                    continue;
+                }
+                Err(msg) => {
+                    writeln!(out, "Tokenizer error: {msg}")?;
+                    continue;
+                }
+            };
+
+            // lines holding nothing but comments don't start an entry
+            if tokens.is_empty() {
+                continue;
+            }
+
+            // we finish once the entry's nesting level is back at zero
+            if nesting(&tokens) == 0 {
+                break;
+            }
+        }
+
+        write!(out, "tokens: [")?;
+        let mut sep = "";
+        for t in &tokens {
+            write!(out, "{sep}{t}")?;
+            sep = ", ";
+        }
+        writeln!(out, "]")?;
+
+        // a type declaration names a type for the entries after it
+        if tokens.first() == Some(&Token::Type) {
+            match parse_type_definition(&tokens, &aliases, &ops) {
+                Ok((name, typ)) => {
+                    writeln!(out, "alias : {name} = {typ}")?;
+                    aliases.insert(name, typ);
+                }
+                Err(msg) => writeln!(out, "Parse error: {msg}")?,
+            }
+            continue;
+        }
+
+        // and an effect declaration an operation they can perform
+        if tokens.first() == Some(&Token::Effect) {
+            match parse_effect_declaration(&tokens, &aliases, &ops) {
+                Ok((name, (param, result))) => {
+                    writeln!(out, "effect: {name}: {param} -> {result}")?;
+                    ops.insert(name, (param, result));
+                }
+                Err(msg) => writeln!(out, "Parse error: {msg}")?,
+            }
+            continue;
+        }
+
+        // parse
+        let parsed = match config.syntax {
+            Syntax::Surface => {
+                parse_expression_at(&tokens, &positions, &aliases, &ops)
+            }
+            Syntax::Sexp => parse_sexp_at(&tokens, &positions, &aliases, &ops),
+        };
+        let mut ast = match parsed {
+            Ok(ast) => ast,
+            Err(msg) => {
+                writeln!(out, "Parse error: {msg}")?;
+                continue;
+            }
+        };
+        writeln!(out, "ast   : {ast}")?;
+
+        // type check; an operation performed must be handled within the
+        // entry, though printing needs no handler. What runs is the checked
+        // term, with casts where it uses dyn values.
+        let t = match elaborate(&ast, &empty_tnv) {
+            Ok((checked, t, effects)) => {
+                match effects.iter().find(|e| *e != IO) {
+                    None => {
+                        ast = checked;
+                        t
+                    }
+                    Some(op) => {
+                        writeln!(
+                            out,
+                            "Type check failure: unhandled effect {op}"
+                        )?;
+                        continue;
+                    }
+                }
+            }
+            Err(msg) => {
+                writeln!(out, "Type check failure: {msg}")?;
+                continue;
+            }
+        };
+        writeln!(out, "type  : {t}")?;
+
+        // in trace mode, reduce step by step, then evaluate what the
+        // reduction stops at: a value, or a stuck term whose error interp
+        // reports
+        if config.trace {
+            loop {
+                let (next, printed) = with_output(|| step(&ast));
+                write!(out, "{printed}")?;
+                let Some(next) = next else { break };
+                writeln!(out, "step  : {next}")?;
+                ast = next;
+            }
+        }
+
+        // evaluate, showing what it prints ahead of the result
+        let use_cps = config.cps || cps::required(&ast);
+        if use_cps && config.strategy != Strategy::ByValue {
+            writeln!(
+                out,
+                "Runtime error: letcc and perform need call-by-value"
+            )?;
+            continue;
+        }
+        let (v, printed) = with_output(|| {
+            if use_cps {
+                cps::eval(&ast, &empty_nv)
+            } else {
+                interp_with(&ast, &empty_nv, config.strategy)
+            }
+        });
+        write!(out, "{printed}")?;
+        let v = match v {
+            Ok(v) => v,
+            Err(msg) => {
+                writeln!(out, "Runtime error: {msg}")?;
+                continue;
+            }
+        };
+        writeln!(out, "result: {v}")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(config: &Config, input: &str) -> String {
+        let mut out = Vec::new();
+        run_with(config, input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sexp_syntax_reads_the_ast_line_back() {
+        let config = Config { syntax: Syntax::Sexp, ..Config::default() };
+        let out = session(&config, "(let f (fn (x: int) (+ x 1))\n (f 2))\n");
+        assert!(out.contains("ast   : (let f (fn (x: int) (+ x 1)) (f 2))\n"));
+        assert!(out.contains("type  : int\nresult: 3\n"));
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let out = session(
+            &Config::default(),
+            "// a note\n1 + /* two\nlines */ 2 // trailing\n/* a\n/* nested */\n*/\n",
+        );
+        assert_eq!(
+            out,
+            "\nPlease enter an expression:\ntokens: [1, +, 2]\nast   : (+ 1 2)\n\
+             type  : int\nresult: 3\n\nPlease enter an expression:\n"
+        );
+    }
+
+    #[test]
+    fn printed_output_comes_before_the_result() {
+        let out = session(
+            &Config::default(),
+            "print(\"a\"); print(1 + 1); print(())\nprint(1); 1 + true\n\
+             print(\"b\"); substring(\"c\", 0, 2)\n",
+        );
+        assert!(out.contains("type  : unit\na\n2\n()\nresult: ()\n"));
+        assert!(out.contains("b\nRuntime error: substring"));
+        assert!(out.contains("Type check failure: not both integers\n"));
+        assert!(!out.contains("\n1\n"));
+    }
+
+    #[test]
+    fn type_declarations_name_types_for_later_entries() {
+        let out = session(
+            &Config::default(),
+            "type IntFn = (int -> int)\ntype Twice = (IntFn -> IntFn)\n\
+             let twice: Twice = fn(f: IntFn) { fn(x: int) { f(f(x)) } } {\n\
+             twice(fn(x: int) { x + 1 })(0) }\n\
+             fn(f: IntFn) { f(\"s\") }\ntype Bad = Nope\n",
+        );
+        assert!(out.contains("alias : IntFn = (int -> int)\n"));
+        assert!(out.contains("alias : Twice = (IntFn -> IntFn)\n"));
+        assert!(out.contains("type  : int\nresult: 2\n"));
+        assert!(out.contains(
+            "Type check failure: function argument type mismatch: \
+             expected int, got str\n"
+        ));
+        assert!(out.contains("Parse error: Unknown type: Nope\n"));
+
+        let sexp = Config { syntax: Syntax::Sexp, ..Config::default() };
+        let out = session(&sexp, "type N = int\n(fn (x: N) x)\n");
+        assert!(out.contains("type  : (N -> N)\n"));
+    }
+
+    #[test]
+    fn trace_shows_each_step() {
+        let trace = Config { trace: true, ..Config::default() };
+        let out = session(
+            &trace,
+            "fn(x: int) { x + x }(12)\nprint(\"a\"); 1 + \"b\"\n\
+             print(1); 9223372036854775807 + 1\n",
+        );
+        assert!(out.contains(
+            "ast   : ((fn (x: int) (+ x x)) 12)\ntype  : int\n\
+             step  : (+ 12 12)\nstep  : 24\nresult: 24\n"
+        ));
+        assert!(out.contains("Type check failure: not both integers\n"));
+        assert!(out.contains(
+            "1\nstep  : (; () (+ 9223372036854775807 1))\n\
+             step  : (+ 9223372036854775807 1)\n\
+             step  : (raise \"integer overflow in 9223372036854775807 + 1\")\n\
+             Runtime error: integer overflow in 9223372036854775807 + 1\n"
+        ));
+    }
+
+    #[test]
+    fn strategy_decides_when_arguments_run() {
+        let input = "fn(x: unit) { x; x; 1 }(print(\"arg\"))\n\
+                     fn(x: str) { 2 }(substring(\"x\", 0, 2))\n";
+        let by = |strategy| Config { strategy, ..Config::default() };
+
+        let out = session(&by(Strategy::ByValue), input);
+        assert!(out.contains("type  : int\narg\nresult: 1\n"));
+        assert!(out.contains("Runtime error: substring"));
+
+        let out = session(&by(Strategy::ByName), input);
+        assert!(out.contains("type  : int\narg\narg\nresult: 1\n"));
+        assert!(out.contains("type  : int\nresult: 2\n"));
+
+        let out = session(&by(Strategy::ByNeed), input);
+        assert!(out.contains("type  : int\narg\nresult: 1\n"));
+        assert!(out.contains("type  : int\nresult: 2\n"));
+    }
+
+    #[test]
+    fn letcc_runs_with_the_cps_interpreter() {
+        let input = "1 + letcc k: cont int { 2 + k(3) }\n\
+                     letcc k: int { 1 }\n";
+        let out = session(&Config { cps: true, ..Config::default() }, input);
+        assert!(out.contains(
+            "ast   : (+ 1 (letcc (k: cont int) (+ 2 (k 3))))\n\
+             type  : int\nresult: 4\n"
+        ));
+        assert!(out.contains(
+            "Type check failure: letcc expects a continuation type, got int\n"
+        ));
+        // an entry using letcc is run by the CPS interpreter anyway
+        let out = session(&Config::default(), input);
+        assert!(out.contains("type  : int\nresult: 4\n"));
+        let by_name =
+            Config { strategy: Strategy::ByName, ..Config::default() };
+        let out = session(&by_name, input);
+        assert!(out.contains(
+            "type  : int\nRuntime error: letcc and perform need call-by-value\n"
+        ));
+        assert!(session(&by_name, "1 + 2\n").contains("result: 3\n"));
+    }
+
+    #[test]
+    fn effects_are_declared_then_handled() {
+        let input = "effect Ask: unit -> int\n\
+                     handle perform Ask(()) + 1 with { Ask(u, k) -> k(41) }\n\
+                     perform Ask(())\n\
+                     perform Tell(1)\n";
+        let out = session(&Config { cps: true, ..Config::default() }, input);
+        assert!(out.contains("effect: Ask: unit -> int\n"));
+        assert!(out.contains("type  : int\nresult: 42\n"));
+        assert!(out.contains("Type check failure: unhandled effect Ask\n"));
+        assert!(out.contains("Parse error: Unknown effect: Tell\n"));
+        // as is an entry performing an operation
+        let out = session(&Config::default(), input);
+        assert!(out.contains("type  : int\nresult: 42\n"));
+    }
+
+    #[test]
+    fn dyn_values_are_checked_where_they_are_used() {
+        // the cast blames the line and column of the n passed, counting
+        // the lines of earlier entries
+        let input = "1\nlet n = (\"7\" : dyn) {\n  fn(x: int) { x + 1 }(n) }\n";
+        let out = session(&Config::default(), input);
+        assert!(out.contains("type  : int\n"));
+        assert!(out.contains(
+            "Runtime error: cast from dyn to int failed at 3:24: got str\n"
+        ));
+        let trace =
+            session(&Config { trace: true, ..Config::default() }, input);
+        assert!(trace.contains(
+            "step  : (raise \"cast from dyn to int failed at 3:24: got str\")\n"
+        ));
+    }
+
+    #[test]
+    fn functions_passed_to_annotated_ones_need_no_annotation() {
+        let input =
+            "fn(f: (int -> int)) { f(1) }(fn(x) { x + 1 })\nfn(x) { x }\n";
+        let out = session(&Config::default(), input);
+        assert!(out.contains("type  : int\nresult: 2\n"));
+        assert!(out.contains(
+            "Type check failure: cannot synthesize a type for fn(x) { x }: \
+             its parameter x has no annotation\n"
+        ));
+    }
+
+    #[test]
+    fn subtype_failures_show_the_failing_component() {
+        let input = "fn(f: (int -> top)) { f(1) }(fn(x: top) { x })\n\
+                     fn(f: (top -> int)) { f(1) }(fn(x: int) { x })\n";
+        let out = session(&Config::default(), input);
+        assert!(out.contains("type  : top\nresult: 1\n"));
+        assert!(out.contains(
+            "Type check failure: function argument type mismatch: \
+             expected (top -> int), got (int -> int): \
+             in the parameter, top is not a subtype of int\n"
+        ));
+    }
+
+    #[test]
+    fn printing_needs_no_handler() {
+        let input = "let f = fn(x: int) { print(x); x } { f }\nprint(2)\n";
+        let out = session(&Config::default(), input);
+        assert!(out.contains("type  : (int -{io}-> int)\n"));
+        assert!(out.contains("type  : unit\n2\nresult: ()\n"));
+    }
+
+    #[test]
+    fn each_syntax_rejects_the_other() {
+        let sexp = Config { syntax: Syntax::Sexp, ..Config::default() };
+        assert!(session(&sexp, "1 + 2\n").contains("Parse error"));
+        assert!(
+            session(&Config::default(), "(+ 1 2)\n").contains("Parse error")
+        );
+    }
+}
@@ -0,0 +1,635 @@
+use super::*;
+use interp::interp;
+use std::collections::HashSet;
+
+// A small-step reducer that rewrites the term itself, substituting
+// arguments for parameters, so every intermediate program can be shown.
+// It is call-by-value and goes left to right, in the order `interp`
+// evaluates. Operators and primitives are left to `interp` once their
+// operands are values. Where `interp` would report a runtime error, the
+// term steps to a raise of the message, and a raise of a str propagates
+// outwards a step at a time until a try catches it.
+
+// The variables of `e` that no enclosing let or fn binds.
+pub fn free_vars(e: &Exp) -> HashSet<String> {
+    match e {
+        Exp::Int(_)
+        | Exp::Float(_)
+        | Exp::Bool(_)
+        | Exp::Str(_)
+        | Exp::Unit => HashSet::new(),
+        Exp::Var(var) => HashSet::from([var.clone()]),
+        Exp::Plus { left, right }
+        | Exp::Concat { left, right }
+        | Exp::LessThan { left, right }
+        | Exp::App { fun: left, arg: right }
+        | Exp::Seq { first: left, second: right } => {
+            let mut vars = free_vars(left);
+            vars.extend(free_vars(right));
+            vars
+        }
+        Exp::Cnd { tst, thn, els } => {
+            let mut vars = free_vars(tst);
+            vars.extend(free_vars(thn));
+            vars.extend(free_vars(els));
+            vars
+        }
+        Exp::Let1 { var, value, body } => {
+            let mut vars = free_vars(body);
+            vars.remove(var);
+            vars.extend(free_vars(value));
+            vars
+        }
+        Exp::Lam { var, body, .. } | Exp::LetCC { var, body, .. } => {
+            let mut vars = free_vars(body);
+            vars.remove(var);
+            vars
+        }
+        Exp::Print { arg: exp }
+        | Exp::Ascribe { exp, .. }
+        | Exp::Raise { arg: exp }
+        | Exp::Cast { exp, .. }
+        | Exp::At { exp, .. } => free_vars(exp),
+        Exp::Try { body, var, handler } => {
+            let mut vars = free_vars(handler);
+            vars.remove(var);
+            vars.extend(free_vars(body));
+            vars
+        }
+        Exp::Perform { arg, .. } => free_vars(arg),
+        Exp::Handle { body, clauses, ret } => {
+            let mut vars = free_vars(body);
+            for Clause { var, k, body, .. } in clauses {
+                let mut clause_vars = free_vars(body);
+                clause_vars.remove(var);
+                clause_vars.remove(k);
+                vars.extend(clause_vars);
+            }
+            if let Some(Return { var, body }) = ret {
+                let mut ret_vars = free_vars(body);
+                ret_vars.remove(var);
+                vars.extend(ret_vars);
+            }
+            vars
+        }
+    }
+}
+
+// Replace the free occurrences of `var` in `e` with `with`, renaming
+// binders that would otherwise capture a free variable of `with`.
+pub fn subst(e: &Exp, var: &str, with: &Exp) -> Exp {
+    let sub = |e: &Exp| Rc::new(subst(e, var, with));
+    match e {
+        Exp::Var(v) if v == var => with.clone(),
+        Exp::Int(_)
+        | Exp::Float(_)
+        | Exp::Bool(_)
+        | Exp::Str(_)
+        | Exp::Unit
+        | Exp::Var(_) => e.clone(),
+        Exp::Plus { left, right } => {
+            Exp::Plus { left: sub(left), right: sub(right) }
+        }
+        Exp::Concat { left, right } => {
+            Exp::Concat { left: sub(left), right: sub(right) }
+        }
+        Exp::LessThan { left, right } => {
+            Exp::LessThan { left: sub(left), right: sub(right) }
+        }
+        Exp::Cnd { tst, thn, els } => {
+            Exp::Cnd { tst: sub(tst), thn: sub(thn), els: sub(els) }
+        }
+        Exp::Let1 { var: bound, value, body } => {
+            let (bound, body) = subst_under(bound, body, var, with);
+            Exp::Let1 { var: bound, value: sub(value), body }
+        }
+        Exp::Lam { var: bound, var_type, body } => {
+            let (bound, body) = subst_under(bound, body, var, with);
+            Exp::Lam { var: bound, var_type: var_type.clone(), body }
+        }
+        Exp::App { fun, arg } => Exp::App { fun: sub(fun), arg: sub(arg) },
+        Exp::Seq { first, second } => {
+            Exp::Seq { first: sub(first), second: sub(second) }
+        }
+        Exp::Print { arg } => Exp::Print { arg: sub(arg) },
+        Exp::Ascribe { exp, typ } => {
+            Exp::Ascribe { exp: sub(exp), typ: typ.clone() }
+        }
+        Exp::LetCC { var: bound, var_type, body } => {
+            let (bound, body) = subst_under(bound, body, var, with);
+            Exp::LetCC { var: bound, var_type: var_type.clone(), body }
+        }
+        Exp::Raise { arg } => Exp::Raise { arg: sub(arg) },
+        Exp::Cast { exp, from, to, label } => Exp::Cast {
+            exp: sub(exp),
+            from: from.clone(),
+            to: to.clone(),
+            label: label.clone(),
+        },
+        Exp::At { pos, exp } => Exp::At { pos: *pos, exp: sub(exp) },
+        Exp::Try { body, var: bound, handler } => {
+            let (bound, handler) = subst_under(bound, handler, var, with);
+            Exp::Try { body: sub(body), var: bound, handler }
+        }
+        Exp::Perform { op, arg, param, result } => Exp::Perform {
+            op: op.clone(),
+            arg: sub(arg),
+            param: param.clone(),
+            result: result.clone(),
+        },
+        Exp::Handle { body, clauses, ret } => Exp::Handle {
+            body: sub(body),
+            clauses: clauses
+                .iter()
+                .map(|c| subst_clause(c, var, with))
+                .collect(),
+            ret: ret.as_ref().map(|Return { var: bound, body }| {
+                let (var, body) = subst_under(bound, body, var, with);
+                Return { var, body }
+            }),
+        },
+    }
+}
+
+// Substitute into a handler clause, which binds two variables: as if it
+// were fn(x) { fn(k) { body } }, renaming either if need be.
+fn subst_clause(clause: &Clause, var: &str, with: &Exp) -> Clause {
+    let Clause { op, param, result, var: x, k, body } = clause;
+    let (x, inner) = subst_under(
+        x,
+        &Rc::new(Exp::Lam {
+            var: k.clone(),
+            var_type: Some(Type::Unit),
+            body: Rc::clone(body),
+        }),
+        var,
+        with,
+    );
+    let Exp::Lam { var: k, body, .. } = Rc::unwrap_or_clone(inner) else {
+        unreachable!("substitution keeps the fn");
+    };
+    let (param, result) = (param.clone(), result.clone());
+    Clause { op: op.clone(), param, result, var: x, k, body }
+}
+
+// Substitute into `body`, which is in the scope of a binder for `bound`.
+// Returns the binder's name, which changes to a fresh one (`x1`, `x2`,
+// ...) if `bound` is free in `with`.
+fn subst_under(
+    bound: &str,
+    body: &Rc<Exp>,
+    var: &str,
+    with: &Exp,
+) -> (String, Rc<Exp>) {
+    let body_vars = free_vars(body);
+    // shadowed, or not mentioned: nothing to replace
+    if bound == var || !body_vars.contains(var) {
+        return (bound.to_string(), Rc::clone(body));
+    }
+    let with_vars = free_vars(with);
+    if !with_vars.contains(bound) {
+        return (bound.to_string(), Rc::new(subst(body, var, with)));
+    }
+    let fresh = (1..)
+        .map(|n| format!("{bound}{n}"))
+        .find(|name| {
+            name != var
+                && !body_vars.contains(name)
+                && !with_vars.contains(name)
+        })
+        .unwrap();
+    let renamed = subst(body, bound, &Exp::Var(fresh.clone()));
+    (fresh, Rc::new(subst(&renamed, var, with)))
+}
+
+// Whether `e` is fully reduced: a literal, a function, or a primitive
+// still waiting for arguments.
+pub fn is_value(e: &Exp) -> bool {
+    match e {
+        Exp::Int(_)
+        | Exp::Float(_)
+        | Exp::Bool(_)
+        | Exp::Str(_)
+        | Exp::Unit
+        | Exp::Lam { .. } => true,
+        // an int? has no literal, so the call that made it stands for it
+        Exp::App { fun, arg }
+            if **fun == Exp::Var("str->int".to_string())
+                && matches!(**arg, Exp::Str(_)) =>
+        {
+            true
+        }
+        _ => prim_args(e).is_some_and(|(name, n)| n < prims::arity(name)),
+    }
+}
+
+// If `e` is a primitive applied to values, its name and how many.
+// Variables left after substitution are unbound, so a primitive's name
+// means the primitive.
+fn prim_args(e: &Exp) -> Option<(&str, usize)> {
+    match e {
+        Exp::Var(name) if prims::prim_type(name).is_some() => Some((name, 0)),
+        Exp::App { fun, arg } if is_value(arg) => {
+            let (name, n) = prim_args(fun)?;
+            Some((name, n + 1))
+        }
+        _ => None,
+    }
+}
+
+// Whether `e` is an exception on its way out: a raise of a str.
+pub fn is_raised(e: &Exp) -> bool {
+    matches!(e, Exp::Raise { arg } if matches!(**arg, Exp::Str(_)))
+}
+
+// Take one step of evaluation, or None if `e` is a value, a raised
+// exception, or is stuck (which only a letcc or a perform is).
+pub fn step(e: &Exp) -> Option<Exp> {
+    if is_value(e) || is_raised(e) {
+        return None;
+    }
+    match e {
+        Exp::Int(_)
+        | Exp::Float(_)
+        | Exp::Bool(_)
+        | Exp::Str(_)
+        | Exp::Unit
+        | Exp::Lam { .. } => None,
+
+        // an unbound variable
+        Exp::Var(_) => delta(e),
+
+        // there is no term for a continuation, so letcc is stuck
+        Exp::LetCC { .. } => None,
+
+        Exp::Plus { left, right } => {
+            step_operands(e, left, right, |left, right| Exp::Plus {
+                left,
+                right,
+            })
+        }
+        Exp::Concat { left, right } => {
+            step_operands(e, left, right, |left, right| Exp::Concat {
+                left,
+                right,
+            })
+        }
+        Exp::LessThan { left, right } => {
+            step_operands(e, left, right, |left, right| Exp::LessThan {
+                left,
+                right,
+            })
+        }
+
+        Exp::Cnd { tst, thn, els } => match **tst {
+            Exp::Bool(true) => Some((**thn).clone()),
+            Exp::Bool(false) => Some((**els).clone()),
+            _ if is_value(tst) => delta(e),
+            _ => step_in(tst, |tst| Exp::Cnd {
+                tst,
+                thn: thn.clone(),
+                els: els.clone(),
+            }),
+        },
+
+        Exp::Let1 { var, value, body } => {
+            if !is_value(value) {
+                return step_in(value, |value| Exp::Let1 {
+                    var: var.clone(),
+                    value,
+                    body: body.clone(),
+                });
+            }
+            Some(subst(body, var, value))
+        }
+
+        Exp::App { fun, arg } => {
+            if !is_value(fun) {
+                return step_in(fun, |fun| Exp::App { fun, arg: arg.clone() });
+            }
+            if !is_value(arg) {
+                return step_in(arg, |arg| Exp::App { fun: fun.clone(), arg });
+            }
+            match &**fun {
+                Exp::Lam { var, body, .. } => Some(subst(body, var, arg)),
+                _ => delta(e),
+            }
+        }
+
+        Exp::Seq { first, second } => {
+            if !is_value(first) {
+                return step_in(first, |first| Exp::Seq {
+                    first,
+                    second: second.clone(),
+                });
+            }
+            Some((**second).clone())
+        }
+
+        Exp::Print { arg } => {
+            if !is_value(arg) {
+                return step_in(arg, |arg| Exp::Print { arg });
+            }
+            delta(e)
+        }
+
+        // types are only checked statically
+        Exp::Ascribe { exp, typ } => {
+            if !is_value(exp) {
+                return step_in(exp, |exp| Exp::Ascribe {
+                    exp,
+                    typ: typ.clone(),
+                });
+            }
+            Some((**exp).clone())
+        }
+
+        // a position only matters to the type checker
+        Exp::At { exp, .. } => Some((**exp).clone()),
+
+        // a raise of anything but a str is a runtime error
+        Exp::Raise { arg } => {
+            if !is_value(arg) {
+                return step_in(arg, |arg| Exp::Raise { arg });
+            }
+            delta(e)
+        }
+
+        // a function is wrapped as interp wraps it; any other value is
+        // checked by running the cast
+        Exp::Cast { exp, from, to, label } => {
+            if !is_value(exp) {
+                return step_in(exp, |exp| Exp::Cast {
+                    exp,
+                    from: from.clone(),
+                    to: to.clone(),
+                    label: label.clone(),
+                });
+            }
+            let is_fun =
+                matches!(**exp, Exp::Lam { .. }) || prim_args(exp).is_some();
+            match to.resolve() {
+                Type::Dyn | Type::Top => Some((**exp).clone()),
+                Type::Fun { .. } if is_fun && from == to => {
+                    Some((**exp).clone())
+                }
+                Type::Fun { .. } if is_fun => {
+                    let param = match &**exp {
+                        Exp::Lam { var_type, .. } => var_type.as_ref(),
+                        _ => None,
+                    };
+                    let fun = (**exp).clone();
+                    Some(interp::cast_wrapper(fun, param, from, to, label))
+                }
+                _ => delta(e),
+            }
+        }
+
+        // there is no term for a resumption either, so a perform is stuck
+        Exp::Perform { op, arg, param, result } => {
+            if !is_value(arg) {
+                return step_in(arg, |arg| Exp::Perform {
+                    op: op.clone(),
+                    arg,
+                    param: param.clone(),
+                    result: result.clone(),
+                });
+            }
+            None
+        }
+
+        Exp::Handle { body, clauses, ret } => {
+            if !is_value(body) {
+                return step_in(body, |body| Exp::Handle {
+                    body,
+                    clauses: clauses.clone(),
+                    ret: ret.clone(),
+                });
+            }
+            Some(match ret {
+                Some(Return { var, body: ret_body }) => {
+                    subst(ret_body, var, body)
+                }
+                None => (**body).clone(),
+            })
+        }
+
+        Exp::Try { body, var, handler } => match &**body {
+            Exp::Raise { arg } if is_raised(body) => {
+                Some(subst(handler, var, arg))
+            }
+            _ if is_value(body) => Some((**body).clone()),
+            _ => Some(Exp::Try {
+                body: Rc::new(step(body)?),
+                var: var.clone(),
+                handler: handler.clone(),
+            }),
+        },
+    }
+}
+
+// Step `sub`, a part of a term that is evaluated first, and put the
+// result back with `make`; an exception raised there escapes the whole
+// term instead.
+fn step_in(sub: &Exp, make: impl FnOnce(Rc<Exp>) -> Exp) -> Option<Exp> {
+    if is_raised(sub) {
+        return Some(sub.clone());
+    }
+    Some(make(Rc::new(step(sub)?)))
+}
+
+// Step the left operand, then the right, then the operator itself.
+fn step_operands(
+    e: &Exp,
+    left: &Rc<Exp>,
+    right: &Rc<Exp>,
+    make: fn(Rc<Exp>, Rc<Exp>) -> Exp,
+) -> Option<Exp> {
+    if !is_value(left) {
+        return step_in(left, |left| make(left, Rc::clone(right)));
+    }
+    if !is_value(right) {
+        return step_in(right, |right| make(Rc::clone(left), right));
+    }
+    delta(e)
+}
+
+// Reduce an operator, primitive or print applied to values by running it,
+// raising the message if it fails.
+fn delta(e: &Exp) -> Option<Exp> {
+    match interp(e, &Env::new()) {
+        Ok(v) => to_exp(v),
+        Err(msg) => Some(Exp::Raise { arg: Rc::new(Exp::Str(msg)) }),
+    }
+}
+
+// Write a value back as a term. Operators and primitives never return
+// closures or continuations, and call-by-value never makes thunks, so
+// those aren't needed.
+fn to_exp(v: Value) -> Option<Exp> {
+    match v {
+        Value::Int(n) => Some(Exp::Int(n)),
+        Value::Float(x) => Some(Exp::Float(x)),
+        Value::Bool(b) => Some(Exp::Bool(b)),
+        Value::Str(s) => Some(Exp::Str(s)),
+        Value::Unit => Some(Exp::Unit),
+        Value::IntOption(n) => {
+            let digits = n.map_or(String::new(), |n| n.to_string());
+            let fun = Rc::new(Exp::Var("str->int".to_string()));
+            Some(Exp::App { fun, arg: Rc::new(Exp::Str(digits)) })
+        }
+        Value::Fun { .. }
+        | Value::Thunk { .. }
+        | Value::Cont { .. }
+        | Value::Resume { .. } => None,
+        Value::Prim { name, args } => {
+            let mut e = Exp::Var(name);
+            for arg in args {
+                e = Exp::App { fun: Rc::new(e), arg: Rc::new(to_exp(arg)?) };
+            }
+            Some(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::with_output;
+    use crate::parse::{parse_expression, parse_sexp, tokenize};
+
+    fn parse(input: &str) -> Exp {
+        parse_expression(&tokenize(input).unwrap()).unwrap()
+    }
+
+    fn sexp(input: &str) -> Exp {
+        parse_sexp(&tokenize(input).unwrap()).unwrap()
+    }
+
+    // Every term `input` steps through, shown as the REPL shows them.
+    fn trace(input: &str) -> Vec<String> {
+        let mut term = parse(input);
+        let mut terms = vec![];
+        while let Some(next) = step(&term) {
+            terms.push(next.to_string());
+            term = next;
+        }
+        terms
+    }
+
+    #[test]
+    fn substitution_avoids_capture() {
+        let x = Exp::Var("x".to_string());
+        assert_eq!(subst(&sexp("(+ x y)"), "x", &Exp::Int(1)), sexp("(+ 1 y)"));
+        // bound occurrences are left alone
+        assert_eq!(
+            subst(&sexp("(fn (x: int) x)"), "x", &Exp::Int(1)),
+            sexp("(fn (x: int) x)")
+        );
+        assert_eq!(
+            subst(&sexp("(let x x x)"), "x", &Exp::Int(1)),
+            sexp("(let x 1 x)")
+        );
+        // y would capture the y being substituted in, so it is renamed
+        assert_eq!(
+            subst(&sexp("(fn (y: int) (+ x y))"), "x", &sexp("(+ y 1)")),
+            sexp("(fn (y1: int) (+ (+ y 1) y1))")
+        );
+        // ...to a name not already in use
+        assert_eq!(
+            subst(&sexp("(fn (y: int) (+ (+ x y) y1))"), "x", &sexp("y")),
+            sexp("(fn (y2: int) (+ (+ y y2) y1))")
+        );
+        assert_eq!(
+            free_vars(&sexp("(let y x (fn (z: int) (+ y z)))")),
+            HashSet::from(["x".to_string()])
+        );
+        assert_eq!(subst(&x, "x", &sexp("length")), sexp("length"));
+    }
+
+    #[test]
+    fn steps_reduce_call_by_value() {
+        assert_eq!(trace("fn(x: int) { x + x }(12)"), ["(+ 12 12)", "24"]);
+        assert_eq!(
+            trace("let f = fn(x: int) { x + 1 } { f(f(1 + 1)) }"),
+            [
+                "((fn (x: int) (+ x 1)) ((fn (x: int) (+ x 1)) (+ 1 1)))",
+                "((fn (x: int) (+ x 1)) ((fn (x: int) (+ x 1)) 2))",
+                "((fn (x: int) (+ x 1)) (+ 2 1))",
+                "((fn (x: int) (+ x 1)) 3)",
+                "(+ 3 1)",
+                "4",
+            ]
+        );
+        assert_eq!(
+            trace("if 1 < 2 { \"a\" ++ \"b\" } else { \"c\" }"),
+            ["(if true (++ \"a\" \"b\") \"c\")", "(++ \"a\" \"b\")", "\"ab\""]
+        );
+        assert_eq!(
+            trace("(substring(\"abc\")(1)(2) : str)"),
+            ["(: \"b\" str)", "\"b\""]
+        );
+        assert_eq!(trace("fn(x: int) { x }"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn partial_primitives_are_values() {
+        assert!(is_value(&parse("substring(\"abc\")(1)")));
+        assert!(!is_value(&parse("substring(\"abc\")(1)(2)")));
+        assert!(!is_value(&parse("substring(\"abc\")(1 + 1)")));
+        assert!(!is_value(&parse("x")));
+    }
+
+    #[test]
+    fn checked_integers_are_their_calls() {
+        assert!(is_value(&parse("str->int(\"x\")")));
+        assert!(!is_value(&parse("str->int(int->str(1))")));
+        assert_eq!(
+            trace("is-int(str->int(int->str(1)))"),
+            vec!["(is-int (str->int \"1\"))", "true"]
+        );
+    }
+
+    #[test]
+    fn print_happens_when_its_step_is_taken() {
+        let term = parse("print(1 + 1); 3");
+        let next = step(&term).unwrap();
+        assert_eq!(next.to_string(), "(; (print 2) 3)");
+        assert_eq!(
+            with_output(|| step(&next)),
+            (Some(sexp("(; () 3)")), "2\n".to_string())
+        );
+    }
+
+    #[test]
+    fn runtime_errors_raise_their_message() {
+        let raised = |msg: &str| {
+            Some(Exp::Raise { arg: Rc::new(Exp::Str(msg.to_string())) })
+        };
+        assert_eq!(
+            step(&parse("9223372036854775807 + 1")),
+            raised("integer overflow in 9223372036854775807 + 1")
+        );
+        assert_eq!(
+            step(&parse("if 0 { 1 } else { 2 }")),
+            raised("boolean expected, found Int(0)")
+        );
+        assert_eq!(step(&parse("y")), raised("y not bound"));
+        assert_eq!(step(&parse("raise \"x\"")), None);
+    }
+
+    #[test]
+    fn exceptions_propagate_to_the_nearest_try() {
+        assert_eq!(
+            trace("try { 1 + (9223372036854775807 + 1 + 2) } catch (e) { e }"),
+            [
+                "(try (+ 1 (+ (raise \"integer overflow in 9223372036854775807 + 1\") 2)) e e)",
+                "(try (+ 1 (raise \"integer overflow in 9223372036854775807 + 1\")) e e)",
+                "(try (raise \"integer overflow in 9223372036854775807 + 1\") e e)",
+                "\"integer overflow in 9223372036854775807 + 1\"",
+            ]
+        );
+        assert_eq!(trace("try { 1 } catch (e) { 2 }"), ["1"]);
+        assert_eq!(trace("let x = raise \"a\" { x }"), ["(raise \"a\")"]);
+    }
+}
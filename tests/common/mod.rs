@@ -0,0 +1,555 @@
+// Helpers shared by the property tests: a small deterministic random
+// number generator, a type-directed generator of well-typed terms and a
+// greedy shrinker for counterexamples.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use student::parse::tokenize;
+use student::prims;
+use student::repl::nesting;
+use student::{Clause, EffectRow, Exp, Ops, Return, TEnv, Type};
+
+// Variable names are drawn from a small pool so that generated terms
+// shadow and capture each other often.
+const NAMES: [&str; 4] = ["a", "b", "x", "y"];
+
+// Primitives that can't fail at run time, so that generated terms using
+// them stay sound.
+const TOTAL_PRIMS: [&str; 5] =
+    ["length", "str-eq", "int->str", "str->int", "int->float"];
+
+const FLOATS: [f64; 5] = [0.0, -1.5, 0.25, 1e20, 2.5e-3];
+const WORDS: [&str; 5] = ["", "a", "hi", "foo bar", "say \"hi\"\n\t\\"];
+
+// The only exceptions generated terms raise, so that an error with any
+// other message is a runtime error a well-typed term shouldn't have.
+pub const EXCEPTIONS: [&str; 2] = ["oops", "no good"];
+
+// The one effect operation generated terms perform, taking and giving an
+// int, and the name its clauses bind the resumption to.
+const ASK: &str = "Ask";
+const RESUME: &str = "k";
+
+// Bound in the type environment, to a type no term asks for, while
+// generating inside a handler for ASK but not inside a function there,
+// so that a function's type never has effects.
+const PERFORMING: &str = "performing";
+
+// The declarations generated terms are parsed with.
+pub fn ops() -> Ops {
+    Ops::from([(ASK.to_string(), (Type::Int, Type::Int))])
+}
+
+// Every entry of inputs/*.input, split as by `entries`.
+pub fn suite_entries() -> Vec<String> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("inputs");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "input"))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .flat_map(|p| entries(&fs::read_to_string(p).unwrap()))
+        .collect()
+}
+
+// Split source text where the REPL would: at the end of a line that leaves
+// parentheses and braces balanced.
+pub fn entries(src: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    let mut entry = String::new();
+    let mut tokens = Vec::new();
+    for line in src.lines() {
+        let Ok(line_tokens) = tokenize(line.trim()) else {
+            continue;
+        };
+        entry.push_str(line);
+        entry.push('\n');
+        tokens.extend(line_tokens);
+        if nesting(&tokens) == 0 && !entry.trim().is_empty() {
+            entries.push(std::mem::take(&mut entry));
+            tokens.clear();
+        }
+    }
+    entries
+}
+
+// xorshift64*; good enough to pick grammar productions.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+fn fun(param: Type, result: Type) -> Type {
+    Type::Fun {
+        param: Box::new(param),
+        result: Box::new(result),
+        effects: EffectRow::new(),
+    }
+}
+
+pub fn gen_type(rng: &mut Rng, depth: usize) -> Type {
+    match rng.below(if depth == 0 { 6 } else { 8 }) {
+        0 => Type::Int,
+        1 => Type::Float,
+        2 => Type::Bool,
+        3 => Type::Str,
+        4 => Type::Unit,
+        5 => Type::Top,
+        _ => fun(gen_type(rng, depth - 1), gen_type(rng, depth - 1)),
+    }
+}
+
+// A type whose values will do where a `ty` is wanted: `ty` itself, any
+// type for a top, or a function taking more and returning less.
+fn gen_subtype(rng: &mut Rng, ty: &Type) -> Type {
+    match ty {
+        Type::Top => gen_type(rng, 1),
+        Type::Fun { param, result, effects } => Type::Fun {
+            param: Box::new(gen_supertype(rng, param)),
+            result: Box::new(gen_subtype(rng, result)),
+            effects: effects.clone(),
+        },
+        _ => ty.clone(),
+    }
+}
+
+// A type that will take any value of a `ty`: `ty` itself, top, or a
+// function taking less and returning more.
+fn gen_supertype(rng: &mut Rng, ty: &Type) -> Type {
+    match ty {
+        _ if rng.chance(20) => Type::Top,
+        Type::Fun { param, result, effects } => Type::Fun {
+            param: Box::new(gen_subtype(rng, param)),
+            result: Box::new(gen_supertype(rng, result)),
+            effects: effects.clone(),
+        },
+        _ => ty.clone(),
+    }
+}
+
+// Generate a term of type `ty` under `tnv`, recursing at most `depth`
+// levels before falling back to literals, variables and lambdas.
+pub fn gen_exp(rng: &mut Rng, ty: &Type, tnv: &TEnv, depth: usize) -> Exp {
+    gen_term(rng, ty, tnv, depth, false)
+}
+
+// `gen_exp`, for a place the checker gives the type `ty` if `checked`, as
+// it does an argument or an ascribed term, so that a fn there may leave
+// its parameter unannotated.
+fn gen_term(
+    rng: &mut Rng,
+    ty: &Type,
+    tnv: &TEnv,
+    depth: usize,
+    checked: bool,
+) -> Exp {
+    if depth == 0 || rng.chance(20) {
+        return gen_leaf(rng, ty, tnv, depth, checked);
+    }
+    let d = depth - 1;
+    match rng.below(15) {
+        0 => gen_operator(rng, ty, tnv, d, checked),
+        1 => {
+            let tst = Rc::new(gen_exp(rng, &Type::Bool, tnv, d));
+            let thn = Rc::new(gen_term(rng, ty, tnv, d, checked));
+            let els = Rc::new(gen_term(rng, ty, tnv, d, checked));
+            Exp::Cnd { tst, thn, els }
+        }
+        2 => {
+            let var = rng.pick(&NAMES).to_string();
+            let var_ty = gen_type(rng, 1);
+            let value = Rc::new(gen_exp(rng, &var_ty, tnv, d));
+            let mut new_tnv = tnv.clone();
+            new_tnv.insert(var.clone(), var_ty);
+            let body = Rc::new(gen_term(rng, ty, &new_tnv, d, checked));
+            Exp::Let1 { var, value, body }
+        }
+        3 => {
+            let arg_ty = gen_type(rng, 1);
+            let fun_ty = fun(arg_ty.clone(), ty.clone());
+            let fun = Rc::new(gen_exp(rng, &fun_ty, tnv, d));
+            // an argument of a subtype needs no cast
+            let sub_ty = gen_subtype(rng, &arg_ty);
+            let checked = sub_ty == arg_ty;
+            let arg = Rc::new(gen_term(rng, &sub_ty, tnv, d, checked));
+            Exp::App { fun, arg }
+        }
+        4 => {
+            let first_ty = gen_type(rng, 1);
+            let first = Rc::new(gen_exp(rng, &first_ty, tnv, d));
+            let second = Rc::new(gen_term(rng, ty, tnv, d, checked));
+            Exp::Seq { first, second }
+        }
+        5 => {
+            let sub_ty = gen_subtype(rng, ty);
+            let checked = sub_ty == *ty;
+            let exp = Rc::new(gen_term(rng, &sub_ty, tnv, d, checked));
+            Exp::Ascribe { exp, typ: ty.clone() }
+        }
+        6 => {
+            let var = rng.pick(&NAMES).to_string();
+            let var_type = Type::Cont { param: Box::new(ty.clone()) };
+            let mut new_tnv = tnv.clone();
+            new_tnv.insert(var.clone(), var_type.clone());
+            let body = Rc::new(gen_term(rng, ty, &new_tnv, d, true));
+            Exp::LetCC { var, var_type, body }
+        }
+        // throw to a continuation of this type, if there is one in scope
+        7 => {
+            let cont_type = Type::Cont { param: Box::new(ty.clone()) };
+            let mut conts: Vec<&String> = tnv
+                .iter()
+                .filter(|(_, var_ty)| **var_ty == cont_type)
+                .map(|(var, _)| var)
+                .collect();
+            if conts.is_empty() {
+                return gen_leaf(rng, ty, tnv, depth, checked);
+            }
+            conts.sort();
+            let fun = Rc::new(Exp::Var(rng.pick(&conts).to_string()));
+            let arg = Rc::new(gen_term(rng, ty, tnv, d, true));
+            Exp::App { fun, arg }
+        }
+        // ascribed, since a raise on its own has type never
+        8 => {
+            let arg = Rc::new(Exp::Str(rng.pick(&EXCEPTIONS).to_string()));
+            Exp::Ascribe { exp: Rc::new(Exp::Raise { arg }), typ: ty.clone() }
+        }
+        9 => {
+            let body = Rc::new(gen_term(rng, ty, tnv, d, checked));
+            let var = rng.pick(&NAMES).to_string();
+            let mut new_tnv = tnv.clone();
+            new_tnv.insert(var.clone(), Type::Str);
+            let handler = Rc::new(gen_term(rng, ty, &new_tnv, d, checked));
+            Exp::Try { body, var, handler }
+        }
+        10 => {
+            let mut body_tnv = tnv.clone();
+            body_tnv.insert(PERFORMING.to_string(), Type::Never);
+            let body = Rc::new(gen_exp(rng, ty, &body_tnv, d));
+            let var = rng.pick(&NAMES).to_string();
+            let k_type = fun(Type::Int, ty.clone());
+            let mut clause_tnv = tnv.clone();
+            clause_tnv.insert(var.clone(), Type::Int);
+            clause_tnv.insert(RESUME.to_string(), k_type);
+            let clause = Clause {
+                op: ASK.to_string(),
+                param: Type::Int,
+                result: Type::Int,
+                var,
+                k: RESUME.to_string(),
+                body: Rc::new(gen_term(rng, ty, &clause_tnv, d, true)),
+            };
+            let ret = rng.chance(50).then(|| {
+                let var = rng.pick(&NAMES).to_string();
+                let mut ret_tnv = tnv.clone();
+                ret_tnv.insert(var.clone(), ty.clone());
+                Return { var, body: Rc::new(gen_exp(rng, ty, &ret_tnv, d)) }
+            });
+            Exp::Handle { body, clauses: vec![clause], ret }
+        }
+        11 if *ty == Type::Int && tnv.contains_key(PERFORMING) => {
+            Exp::Perform {
+                op: ASK.to_string(),
+                arg: Rc::new(gen_exp(rng, ty, tnv, d)),
+                param: Box::new(Type::Int),
+                result: Box::new(Type::Int),
+            }
+        }
+        12 => gen_apply(rng, ty, tnv, d),
+        // through dyn and back, so that a cast checks it on the way out
+        13 => {
+            let exp = Rc::new(gen_exp(rng, ty, tnv, d));
+            let exp = Rc::new(Exp::Ascribe { exp, typ: Type::Dyn });
+            Exp::Ascribe { exp, typ: ty.clone() }
+        }
+        _ => gen_leaf(rng, ty, tnv, depth, checked),
+    }
+}
+
+// A call through apply = fn(f: (A -{e}-> T)) { fn(x: A) { f(x) } }, whose
+// result has the effects of the function it is passed: none, or Ask if
+// the term may perform it and the function does.
+fn gen_apply(rng: &mut Rng, ty: &Type, tnv: &TEnv, depth: usize) -> Exp {
+    let arg_ty = gen_type(rng, 1);
+    let poly_ty = Type::Fun {
+        param: Box::new(arg_ty.clone()),
+        result: Box::new(ty.clone()),
+        effects: EffectRow::from(["e".to_string()]),
+    };
+    let var = |name: &str| Rc::new(Exp::Var(name.to_string()));
+    let apply = Exp::Lam {
+        var: "f".to_string(),
+        var_type: Some(poly_ty),
+        body: Rc::new(Exp::Lam {
+            var: "x".to_string(),
+            var_type: Some(arg_ty.clone()),
+            body: Rc::new(Exp::App { fun: var("f"), arg: var("x") }),
+        }),
+    };
+    let fun = if tnv.contains_key(PERFORMING) && rng.chance(50) {
+        // a function that may perform, as it is only called here
+        let var = rng.pick(&NAMES).to_string();
+        let mut new_tnv = tnv.clone();
+        new_tnv.remove(RESUME);
+        new_tnv.insert(var.clone(), arg_ty.clone());
+        let body = gen_term(rng, ty, &new_tnv, depth, true);
+        let var_type = rng.chance(50).then(|| arg_ty.clone());
+        Exp::Lam { var, var_type, body: Rc::new(body) }
+    } else {
+        gen_term(rng, &fun(arg_ty.clone(), ty.clone()), tnv, depth, true)
+    };
+    let applied = Exp::App { fun: Rc::new(apply), arg: Rc::new(fun) };
+    let arg = Rc::new(gen_term(rng, &arg_ty, tnv, depth, true));
+    Exp::App { fun: Rc::new(applied), arg }
+}
+
+type BinOp = fn(Rc<Exp>, Rc<Exp>) -> Exp;
+
+fn gen_operator(
+    rng: &mut Rng,
+    ty: &Type,
+    tnv: &TEnv,
+    depth: usize,
+    checked: bool,
+) -> Exp {
+    let (left_ty, make): (Type, BinOp) = match ty {
+        Type::Int => (Type::Int, |left, right| Exp::Plus { left, right }),
+        Type::Float => (Type::Float, |left, right| Exp::Plus { left, right }),
+        Type::Str => (Type::Str, |left, right| Exp::Concat { left, right }),
+        Type::Bool => (
+            if rng.chance(50) { Type::Int } else { Type::Float },
+            |left, right| Exp::LessThan { left, right },
+        ),
+        _ => return gen_leaf(rng, ty, tnv, depth, checked),
+    };
+    let left = Rc::new(gen_exp(rng, &left_ty, tnv, depth));
+    let right = Rc::new(gen_exp(rng, &left_ty, tnv, depth));
+    make(left, right)
+}
+
+fn gen_leaf(
+    rng: &mut Rng,
+    ty: &Type,
+    tnv: &TEnv,
+    depth: usize,
+    checked: bool,
+) -> Exp {
+    let mut in_scope: Vec<&String> = tnv
+        .iter()
+        .filter(|(_, var_ty)| *var_ty == ty)
+        .map(|(var, _)| var)
+        .collect();
+    if !in_scope.is_empty() && rng.chance(50) {
+        in_scope.sort();
+        return Exp::Var(rng.pick(&in_scope).to_string());
+    }
+    match ty {
+        Type::Int => Exp::Int(rng.below(41) as isize - 20),
+        Type::Float => Exp::Float(*rng.pick(&FLOATS)),
+        Type::Bool => Exp::Bool(rng.chance(50)),
+        Type::Str => Exp::Str(rng.pick(&WORDS).to_string()),
+        Type::IntOption => {
+            let word = rng.pick(&["12", "-3", "x"]).to_string();
+            let fun = Rc::new(Exp::Var("str->int".to_string()));
+            Exp::App { fun, arg: Rc::new(Exp::Str(word)) }
+        }
+        Type::Unit => Exp::Unit,
+        Type::Alias { def, .. } => gen_leaf(rng, def, tnv, depth, checked),
+        // a value of some other type, taken as a top
+        Type::Top => {
+            let exp_ty = gen_type(rng, 1);
+            let exp = Rc::new(gen_leaf(rng, &exp_ty, tnv, depth, false));
+            Exp::Ascribe { exp, typ: Type::Top }
+        }
+        Type::Cont { .. } | Type::Never | Type::Dyn => {
+            unreachable!("continuation, never and dyn types aren't generated")
+        }
+        Type::Fun { param, result, .. } => {
+            let prims: Vec<&str> = TOTAL_PRIMS
+                .into_iter()
+                .filter(|name| prims::prim_type(name).as_ref() == Some(ty))
+                .collect();
+            if !prims.is_empty() && rng.chance(50) {
+                return Exp::Var(rng.pick(&prims).to_string());
+            }
+            let var = rng.pick(&NAMES).to_string();
+            let mut new_tnv = tnv.clone();
+            new_tnv.remove(PERFORMING);
+            new_tnv.remove(RESUME);
+            new_tnv.insert(var.clone(), (**param).clone());
+            // the checker takes the body's type from the one wanted too
+            let depth = depth.saturating_sub(1);
+            let body = gen_term(rng, result, &new_tnv, depth, checked);
+            // where the function type is wanted, the parameter needs none
+            let var_type =
+                (!checked || rng.chance(50)).then(|| (**param).clone());
+            Exp::Lam { var, var_type, body: Rc::new(body) }
+        }
+    }
+}
+
+pub fn size(e: &Exp) -> usize {
+    1 + children(e).iter().map(|c| size(c)).sum::<usize>()
+}
+
+fn children(e: &Exp) -> Vec<&Exp> {
+    match e {
+        Exp::Int(_)
+        | Exp::Float(_)
+        | Exp::Bool(_)
+        | Exp::Str(_)
+        | Exp::Unit
+        | Exp::Var(_) => vec![],
+        Exp::Plus { left, right }
+        | Exp::Concat { left, right }
+        | Exp::LessThan { left, right } => vec![left, right],
+        Exp::Cnd { tst, thn, els } => vec![tst, thn, els],
+        Exp::Let1 { value, body, .. } => vec![value, body],
+        Exp::Lam { body, .. } | Exp::LetCC { body, .. } => vec![body],
+        Exp::App { fun, arg } => vec![fun, arg],
+        Exp::Seq { first, second } => vec![first, second],
+        Exp::Print { arg } | Exp::Raise { arg } => vec![arg],
+        Exp::Ascribe { exp, .. }
+        | Exp::Cast { exp, .. }
+        | Exp::At { exp, .. } => vec![exp],
+        Exp::Try { body, handler, .. } => vec![body, handler],
+        Exp::Perform { arg, .. } => vec![arg],
+        Exp::Handle { body, clauses, ret } => {
+            let mut kids = vec![&**body];
+            kids.extend(clauses.iter().map(|c| &*c.body));
+            kids.extend(ret.iter().map(|r| &*r.body));
+            kids
+        }
+    }
+}
+
+// Rebuild `e` with its children replaced, in the order `children` lists
+// them.
+fn with_children(e: &Exp, mut new: Vec<Exp>) -> Exp {
+    let mut next = || Rc::new(new.remove(0));
+    match e {
+        Exp::Int(_)
+        | Exp::Float(_)
+        | Exp::Bool(_)
+        | Exp::Str(_)
+        | Exp::Unit
+        | Exp::Var(_) => e.clone(),
+        Exp::Plus { .. } => Exp::Plus { left: next(), right: next() },
+        Exp::Concat { .. } => Exp::Concat { left: next(), right: next() },
+        Exp::LessThan { .. } => Exp::LessThan { left: next(), right: next() },
+        Exp::Cnd { .. } => Exp::Cnd { tst: next(), thn: next(), els: next() },
+        Exp::Let1 { var, .. } => {
+            Exp::Let1 { var: var.clone(), value: next(), body: next() }
+        }
+        Exp::Lam { var, var_type, .. } => Exp::Lam {
+            var: var.clone(),
+            var_type: var_type.clone(),
+            body: next(),
+        },
+        Exp::App { .. } => Exp::App { fun: next(), arg: next() },
+        Exp::Seq { .. } => Exp::Seq { first: next(), second: next() },
+        Exp::Print { .. } => Exp::Print { arg: next() },
+        Exp::Ascribe { typ, .. } => {
+            Exp::Ascribe { exp: next(), typ: typ.clone() }
+        }
+        Exp::LetCC { var, var_type, .. } => Exp::LetCC {
+            var: var.clone(),
+            var_type: var_type.clone(),
+            body: next(),
+        },
+        Exp::Raise { .. } => Exp::Raise { arg: next() },
+        Exp::Cast { from, to, label, .. } => Exp::Cast {
+            exp: next(),
+            from: from.clone(),
+            to: to.clone(),
+            label: label.clone(),
+        },
+        Exp::At { pos, .. } => Exp::At { pos: *pos, exp: next() },
+        Exp::Try { var, .. } => {
+            Exp::Try { body: next(), var: var.clone(), handler: next() }
+        }
+        Exp::Perform { op, param, result, .. } => Exp::Perform {
+            op: op.clone(),
+            arg: next(),
+            param: param.clone(),
+            result: result.clone(),
+        },
+        Exp::Handle { clauses, ret, .. } => Exp::Handle {
+            body: next(),
+            clauses: clauses
+                .iter()
+                .map(|c| Clause { body: next(), ..c.clone() })
+                .collect(),
+            ret: ret
+                .as_ref()
+                .map(|r| Return { var: r.var.clone(), body: next() }),
+        },
+    }
+}
+
+// All terms one shrinking step away from `e`: the node replaced by one
+// of its children, a literal made simpler, or one child shrunk.
+fn shrink_candidates(e: &Exp) -> Vec<Exp> {
+    let mut out = Vec::new();
+    match e {
+        Exp::Int(n) if *n != 0 => out.push(Exp::Int(n / 2)),
+        Exp::Float(x) if *x != 0.0 => out.push(Exp::Float(0.0)),
+        Exp::Bool(true) => out.push(Exp::Bool(false)),
+        Exp::Str(s) if !s.is_empty() => out.push(Exp::Str(String::new())),
+        _ => {}
+    }
+    let kids = children(e);
+    out.extend(kids.iter().map(|&c| c.clone()));
+    for (i, kid) in kids.iter().enumerate() {
+        for smaller in shrink_candidates(kid) {
+            let mut new: Vec<Exp> = kids.iter().map(|&c| c.clone()).collect();
+            new[i] = smaller;
+            out.push(with_children(e, new));
+        }
+    }
+    out
+}
+
+// Greedily shrink a counterexample: repeatedly move to the first smaller
+// candidate that still fails, until none does.
+pub fn shrink(e: Exp, fails: impl Fn(&Exp) -> bool) -> Exp {
+    let mut current = e;
+    loop {
+        let next = shrink_candidates(&current)
+            .into_iter()
+            .find(|c| size(c) <= size(&current) && *c != current && fails(c));
+        match next {
+            Some(smaller) => current = smaller,
+            None => return current,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// Runs the fuzz seed corpus, and cheap random mutations of it, through the
+// same entry points as the fuzz/ targets so that stable `cargo test` catches
+// panics and stack overflows without needing cargo-fuzz.
+
+mod common;
+
+use common::Rng;
+use std::fs;
+use std::path::Path;
+use student::interp::STACK_SIZE;
+use student::parse::{parse_expression, tokenize};
+
+const MUTANTS_PER_SEED: u64 = 200;
+
+// Characters worth splicing into inputs: every piece of punctuation the
+// tokenizer knows, plus a few it doesn't.
+const ALPHABET: &[&str] = &[
+    "(", ")", "{", "}", "+", "++", "<", ":", "->", "=", "-", "\"", " ", "\n",
+    "if", "else", "let", "fn", "int", "x", "1", "é", "#", "((((", "}}}}", "//",
+    "/*", "*/", ",", ".", "1.5e3", "\\", ";", "()", "print", "type", "letcc",
+    "raise", "try", "catch", "cont", "effect", "perform", "handle", "with",
+    "return", "-{", "Op", "io", "dyn", "top",
+];
+
+fn seeds() -> Vec<String> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/seeds");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .expect("cannot read fuzz/seeds")
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    paths.iter().map(|p| fs::read_to_string(p).unwrap()).collect()
+}
+
+fn mutate(rng: &mut Rng, seed: &str) -> String {
+    let mut chars: Vec<String> = seed.chars().map(String::from).collect();
+    for _ in 0..=rng.below(4) {
+        let at = rng.below(chars.len() + 1);
+        match rng.below(3) {
+            0 if at < chars.len() => {
+                chars.remove(at);
+            }
+            1 if at < chars.len() => {
+                chars[at] = rng.pick(ALPHABET).to_string();
+            }
+            _ => chars.insert(at, rng.pick(ALPHABET).to_string()),
+        }
+    }
+    chars.concat()
+}
+
+// The three fuzz targets, one after another.
+fn run_targets(input: &str) {
+    if let Ok(tokens) = tokenize(input) {
+        for t in &tokens {
+            let _ = t.to_string();
+        }
+        if let Ok(ast) = parse_expression(&tokens) {
+            let _ = ast.to_string();
+        }
+    }
+    let mut out = Vec::new();
+    student::repl::run(input.as_bytes(), &mut out).unwrap();
+}
+
+#[test]
+fn seed_corpus_is_not_empty() {
+    assert!(seeds().len() >= 50);
+}
+
+#[test]
+fn seeds_survive_every_target() {
+    for seed in seeds() {
+        run_targets(&seed);
+    }
+}
+
+#[test]
+fn mutated_seeds_survive_every_target() {
+    for (i, seed) in seeds().iter().enumerate() {
+        let mut rng = Rng::new(i as u64);
+        for _ in 0..MUTANTS_PER_SEED {
+            run_targets(&mutate(&mut rng, seed));
+        }
+    }
+}
+
+// The binary runs the REPL on a thread with STACK_SIZE of stack, while
+// test threads get 2 MiB, which unoptimized builds can use up before the
+// parser's nesting limit is reached.
+#[test]
+fn deeply_nested_input_is_rejected_without_overflow() {
+    let nested = || {
+        for n in [150, 199, 200, 1_000, 100_000] {
+            run_targets(&format!("{}1{}", "(".repeat(n), ")".repeat(n)));
+            run_targets(&vec!["1"; n].join(" + "));
+            run_targets(&format!(
+                "let f = fn(x: int) {{ x }} {{ f{} }}",
+                "(1)".repeat(n)
+            ));
+            run_targets(&"let x = 1 { ".repeat(n));
+            run_targets(&format!("f({}1)", "1, ".repeat(n)));
+            run_targets(&format!("{}1", "print(1); ".repeat(n)));
+            run_targets(&format!("fn({}x: int) {{ x }}", "x: int, ".repeat(n)));
+            run_targets(&format!(
+                "{}{}",
+                "if true { ".repeat(n),
+                "1 } else { 2 }".repeat(n)
+            ));
+        }
+    };
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(nested)
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,168 @@
+// The small-step reducer agrees with `interp`: stepping a term until it
+// stops gives the value, the error and the printed output that evaluating
+// the term directly does.
+
+mod common;
+
+use common::{Rng, gen_exp, gen_type, shrink, suite_entries};
+use student::interp::{elaborate, interp, with_output};
+use student::parse::{
+    parse_effect_declaration, parse_expression_with, parse_type_definition,
+    tokenize,
+};
+use student::prims;
+use student::step::{is_raised, is_value, step};
+use student::{Aliases, Env, Exp, Ops, TEnv, Token, Type, Value};
+
+const CASES: u64 = 1000;
+
+// Evaluate by stepping, as the REPL's trace mode does. Stepping has to
+// stop at a value, a raised exception, or a letcc or perform that it is
+// stuck on, which `interp` can't run either.
+fn reduce(e: &Exp) -> Result<Value, String> {
+    let mut term = e.clone();
+    while let Some(next) = step(&term) {
+        term = next;
+    }
+    if is_value(&term) {
+        return Ok(value(&term));
+    }
+    match &term {
+        Exp::Raise { arg } if is_raised(&term) => {
+            let Exp::Str(msg) = &**arg else { unreachable!() };
+            return Err(msg.clone());
+        }
+        _ => {}
+    }
+    match stuck_on(&term) {
+        Some(Exp::LetCC { .. }) => {
+            Err("letcc needs the CPS interpreter".to_string())
+        }
+        Some(Exp::Perform { .. }) => {
+            Err("perform needs the CPS interpreter".to_string())
+        }
+        _ => panic!("stepping stopped at {term}, which can still be reduced"),
+    }
+}
+
+// The value a fully reduced term stands for.
+fn value(e: &Exp) -> Value {
+    match e {
+        Exp::Int(n) => Value::Int(*n),
+        Exp::Float(x) => Value::Float(*x),
+        Exp::Bool(b) => Value::Bool(*b),
+        Exp::Str(s) => Value::Str(s.clone()),
+        Exp::Unit => Value::Unit,
+        Exp::Lam { var, var_type, body } => Value::Fun {
+            var: var.clone(),
+            var_type: var_type.clone().unwrap_or(Type::Dyn),
+            body: body.clone(),
+            nv: Env::new(),
+        },
+        Exp::Var(name) => Value::Prim { name: name.clone(), args: vec![] },
+        // a primitive short of arguments, or str->int of a literal
+        Exp::App { fun, arg } => {
+            let Value::Prim { name, mut args } = value(fun) else {
+                unreachable!("{e} is not a value")
+            };
+            if args.len() + 1 < prims::arity(&name) {
+                args.push(value(arg));
+                Value::Prim { name, args }
+            } else {
+                prims::apply(&name, &args, value(arg)).unwrap()
+            }
+        }
+        _ => unreachable!("{e} is not a value"),
+    }
+}
+
+// The letcc or perform that `e` is stuck on: the part of it evaluated
+// next, in the order `step` goes.
+fn stuck_on(e: &Exp) -> Option<&Exp> {
+    let next = match e {
+        Exp::LetCC { .. } => return Some(e),
+        Exp::Perform { arg, .. } if is_value(arg) => return Some(e),
+        Exp::Plus { left, right }
+        | Exp::Concat { left, right }
+        | Exp::LessThan { left, right } => vec![left, right],
+        Exp::App { fun, arg } => vec![fun, arg],
+        Exp::Cnd { tst: sub, .. }
+        | Exp::Let1 { value: sub, .. }
+        | Exp::Seq { first: sub, .. }
+        | Exp::Print { arg: sub }
+        | Exp::Raise { arg: sub }
+        | Exp::Ascribe { exp: sub, .. }
+        | Exp::Cast { exp: sub, .. }
+        | Exp::Perform { arg: sub, .. }
+        | Exp::Handle { body: sub, .. }
+        | Exp::Try { body: sub, .. } => vec![sub],
+        _ => vec![],
+    };
+    stuck_on(next.into_iter().find(|sub| !is_value(sub))?)
+}
+
+// Closures are compared by their code alone: interp's capture their
+// environment, where the reducer has substituted it in.
+fn same(l: &Result<Value, String>, r: &Result<Value, String>) -> bool {
+    match (l, r) {
+        (
+            Ok(Value::Fun { var, var_type, .. }),
+            Ok(Value::Fun { var: v, var_type: t, .. }),
+        ) => var == v && var_type == t,
+        _ => l == r,
+    }
+}
+
+fn agrees(e: &Exp) -> bool {
+    let direct = with_output(|| interp(e, &Env::new()));
+    let stepped = with_output(|| reduce(e));
+    same(&direct.0, &stepped.0) && direct.1 == stepped.1
+}
+
+#[test]
+fn stepping_agrees_with_interp_on_suite_inputs() {
+    let mut aliases = Aliases::new();
+    let mut ops = Ops::new();
+    let mut checked = 0;
+    for source in suite_entries() {
+        let Ok(tokens) = tokenize(&source) else {
+            continue;
+        };
+        if tokens.first() == Some(&Token::Type) {
+            if let Ok((name, typ)) =
+                parse_type_definition(&tokens, &aliases, &ops)
+            {
+                aliases.insert(name, typ);
+            }
+            continue;
+        }
+        if tokens.first() == Some(&Token::Effect) {
+            if let Ok((name, sig)) =
+                parse_effect_declaration(&tokens, &aliases, &ops)
+            {
+                ops.insert(name, sig);
+            }
+            continue;
+        }
+        if let Ok(e) = parse_expression_with(&tokens, &aliases, &ops) {
+            assert!(agrees(&e), "{source}");
+            checked += 1;
+        }
+    }
+    assert!(checked >= 50);
+}
+
+#[test]
+fn stepping_agrees_with_interp_on_generated_terms() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let t = gen_type(&mut rng, 2);
+        let e = gen_exp(&mut rng, &t, &TEnv::new(), 5);
+        // with the casts the type checker puts in
+        let (e, _, _) = elaborate(&e, &TEnv::new()).unwrap();
+        if !agrees(&e) {
+            let small = shrink(e, |c| !agrees(c));
+            panic!("seed {seed}: {small} steps to a different result");
+        }
+    }
+}
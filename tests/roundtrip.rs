@@ -0,0 +1,103 @@
+// parse(print(e)) == e for the surface-syntax pretty printer, and
+// parse_sexp(e.to_string()) == e for Display, over random well-typed terms
+// and every entry of the transcript suites. Formatting a whole suite must
+// likewise keep every entry's AST.
+
+mod common;
+
+use common::{Rng, entries, gen_exp, gen_type, ops, shrink, suite_entries};
+use std::fs;
+use std::path::Path;
+use student::format::format_source;
+use student::parse::{parse_expression_with, parse_sexp_with, tokenize};
+use student::pretty::Printer;
+use student::{Aliases, Exp, TEnv};
+
+const CASES: u64 = 1000;
+
+fn printers() -> Vec<Printer> {
+    vec![
+        Printer::default(),
+        Printer { indent: 2, width: 0 },
+        Printer { indent: 3, width: 30 },
+    ]
+}
+
+fn parse(input: &str) -> Result<Exp, String> {
+    parse_expression_with(&tokenize(input)?, &Aliases::new(), &ops())
+}
+
+fn round_trips(e: &Exp) -> bool {
+    printers().iter().all(|p| parse(&p.print(e)).as_ref() == Ok(e))
+}
+
+#[test]
+fn generated_terms_round_trip() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let t = gen_type(&mut rng, 2);
+        let e = gen_exp(&mut rng, &t, &TEnv::new(), 5);
+        if !round_trips(&e) {
+            let small = shrink(e, |c| !round_trips(c));
+            let printed: Vec<String> =
+                printers().iter().map(|p| p.print(&small)).collect();
+            panic!("seed {seed}: {small} prints as {printed:?}");
+        }
+    }
+}
+
+#[test]
+fn suite_inputs_round_trip() {
+    let entries = suite_entries();
+    assert!(entries.len() >= 50);
+    for source in entries {
+        if let Ok(e) = parse(&source) {
+            assert!(round_trips(&e), "{source}");
+        }
+    }
+}
+
+fn sexp_round_trips(e: &Exp) -> bool {
+    tokenize(&e.to_string())
+        .and_then(|t| parse_sexp_with(&t, &Aliases::new(), &ops()))
+        .as_ref()
+        == Ok(e)
+}
+
+#[test]
+fn generated_terms_round_trip_through_display() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let t = gen_type(&mut rng, 2);
+        let e = gen_exp(&mut rng, &t, &TEnv::new(), 5);
+        if !sexp_round_trips(&e) {
+            let small = shrink(e, |c| !sexp_round_trips(c));
+            panic!("seed {seed}: {small} does not read back");
+        }
+    }
+}
+
+#[test]
+fn suite_inputs_round_trip_through_display() {
+    for source in suite_entries() {
+        if let Ok(e) = parse(&source) {
+            assert!(sexp_round_trips(&e), "{source}");
+        }
+    }
+}
+
+#[test]
+fn formatting_suite_inputs_keeps_their_asts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("inputs");
+    for name in ["test_a.input", "test_b.input"] {
+        let src = fs::read_to_string(dir.join(name)).unwrap();
+        let asts = |src: &str| -> Vec<Result<Exp, String>> {
+            entries(src).iter().map(|entry| parse(entry)).collect()
+        };
+        for printer in printers() {
+            let formatted = format_source(&src, &printer).unwrap();
+            assert_eq!(asts(&formatted), asts(&src), "{name}");
+            assert_eq!(format_source(&formatted, &printer), Ok(formatted));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+// Type soundness as a property: every term `tc` accepts at type T must
+// evaluate without a runtime error to a value that inhabits T, unless it
+// ends with an exception it raised itself.
+
+mod common;
+
+use common::{EXCEPTIONS, Rng, gen_exp, gen_type, shrink, size};
+use std::rc::Rc;
+use student::cps;
+use student::interp::{
+    Strategy, elaborate, fits, interp, interp_with, tc, tc_effects,
+};
+use student::prims;
+use student::{EffectRow, Env, Exp, TEnv, Type, Value};
+
+const CASES: u64 = 2000;
+const MAX_DEPTH: usize = 5;
+
+// The type a value would be given if it were written back as a term, or
+// None if it is not well typed (for closures, if the body doesn't check
+// under the types of the captured environment).
+fn value_type(v: &Value) -> Option<Type> {
+    match v {
+        Value::Int(_) => Some(Type::Int),
+        Value::Float(_) => Some(Type::Float),
+        Value::Bool(_) => Some(Type::Bool),
+        Value::Str(_) => Some(Type::Str),
+        Value::IntOption(_) => Some(Type::IntOption),
+        Value::Unit => Some(Type::Unit),
+        Value::Fun { var, var_type, body, nv } => {
+            let mut tnv = env_types(nv)?;
+            tnv.insert(var.clone(), var_type.clone());
+            let (result, effects) = tc_effects(body, &tnv).ok()?;
+            Some(Type::Fun {
+                param: Box::new(var_type.clone()),
+                result: Box::new(result),
+                effects,
+            })
+        }
+        // a primitive has the rest of its type once its arguments are taken
+        Value::Prim { name, args } => {
+            let mut t = prims::prim_type(name)?;
+            for arg in args {
+                let Type::Fun { param, result, .. } = t else {
+                    return None;
+                };
+                if !inhabits(arg, &param) {
+                    return None;
+                }
+                t = *result;
+            }
+            Some(t)
+        }
+        // a continuation keeps the type letcc gave it
+        Value::Cont { typ, .. } => Some(typ.clone()),
+        // nothing records a resumption's type
+        Value::Resume { .. } => None,
+        // a thunk has the type of the term it will evaluate
+        Value::Thunk { exp, nv, .. } => tc(exp, &env_types(nv)?).ok(),
+    }
+}
+
+fn env_types(nv: &Env) -> Option<TEnv> {
+    let mut tnv = TEnv::new();
+    for (name, v) in nv {
+        // generated functions never use a resumption they capture, so
+        // leaving it out can only make them fail to check
+        if matches!(v, Value::Resume { .. }) {
+            continue;
+        }
+        tnv.insert(name.clone(), value_type(v)?);
+    }
+    Some(tnv)
+}
+
+// Whether `v` will do as a `t`: its type is a subtype of `t`.
+fn inhabits(v: &Value, t: &Type) -> bool {
+    value_type(v).is_some_and(|v_type| fits(&v_type, t))
+}
+
+const STRATEGIES: [Strategy; 3] =
+    [Strategy::ByValue, Strategy::ByName, Strategy::ByNeed];
+
+// Ok if `e` is ill typed, performs an operation it doesn't handle, or
+// behaves as its type predicts under every evaluation strategy and in the
+// CPS interpreter, the only one that runs letcc and effect handlers.
+fn sound(e: &Exp) -> Result<(), String> {
+    let Ok((e, t, effects)) = elaborate(e, &TEnv::new()) else {
+        return Ok(());
+    };
+    let e = &e;
+    if !effects.is_empty() {
+        return Ok(());
+    }
+    let mut runs = vec![("CPS".to_string(), cps::eval(e, &Env::new()))];
+    if !cps::required(e) {
+        for strategy in STRATEGIES {
+            let v = interp_with(e, &Env::new(), strategy);
+            runs.push((format!("{strategy:?}"), v));
+        }
+    }
+    for (how, result) in runs {
+        match result {
+            Ok(v) if inhabits(&v, &t) => {}
+            Err(msg) if EXCEPTIONS.contains(&msg.as_str()) => {}
+            Ok(v) => {
+                return Err(format!("{how}: type {t} predicted, but got {v}"));
+            }
+            Err(msg) => {
+                return Err(format!(
+                    "{how}: type {t} predicted, but got error: {msg}"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn generated_terms_are_well_typed() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let t = gen_type(&mut rng, 2);
+        let e = gen_exp(&mut rng, &t, &TEnv::new(), MAX_DEPTH);
+        assert_eq!(tc(&e, &TEnv::new()), Ok(t), "seed {seed}: {e}");
+    }
+}
+
+#[test]
+fn well_typed_terms_evaluate_to_their_type() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let t = gen_type(&mut rng, 2);
+        let e = gen_exp(&mut rng, &t, &TEnv::new(), MAX_DEPTH);
+        if let Err(msg) = sound(&e) {
+            let small = shrink(e, |c| sound(c).is_err());
+            panic!(
+                "seed {seed}: {msg}\nshrunk counterexample: {small}\n{}",
+                sound(&small).unwrap_err()
+            );
+        }
+    }
+}
+
+#[test]
+fn inhabits_rejects_mismatched_values() {
+    assert!(inhabits(&Value::Int(1), &Type::Int));
+    assert!(!inhabits(&Value::Int(1), &Type::Bool));
+    let closure = interp(
+        &Exp::Lam {
+            var: "x".to_string(),
+            var_type: Some(Type::Int),
+            body: Rc::new(Exp::Var("x".to_string())),
+        },
+        &Env::new(),
+    )
+    .unwrap();
+    let int_to = |result| Type::Fun {
+        param: Box::new(Type::Int),
+        result: Box::new(result),
+        effects: EffectRow::new(),
+    };
+    assert!(inhabits(&closure, &int_to(Type::Int)));
+    assert!(!inhabits(&closure, &int_to(Type::Str)));
+    // a value will do for any supertype of its own
+    assert!(inhabits(&Value::Int(1), &Type::Top));
+    assert!(inhabits(&closure, &int_to(Type::Top)));
+    assert!(!inhabits(
+        &closure,
+        &Type::Fun {
+            param: Box::new(Type::Top),
+            result: Box::new(Type::Int),
+            effects: EffectRow::new(),
+        }
+    ));
+}
+
+#[test]
+fn shrinking_finds_a_minimal_counterexample() {
+    // pretend any term containing ++ is a bug; the shrinker should boil
+    // a large term down to a single concatenation of empty strings
+    let has_concat = |e: &Exp| e.to_string().contains("++");
+    let mut rng = Rng::new(7);
+    let big = (0..)
+        .map(|_| gen_exp(&mut rng, &Type::Str, &TEnv::new(), MAX_DEPTH))
+        .find(|e| has_concat(e) && size(e) > 10)
+        .unwrap();
+    let small = shrink(big, has_concat);
+    assert_eq!(small.to_string(), "(++ \"\" \"\")");
+}
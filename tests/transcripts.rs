@@ -0,0 +1,221 @@
+// Replays every inputs/*.input file through the REPL and compares the
+// interleaved input/output against the matching .transcript file, in the
+// same format lib/stepper uses:
+//
+//     < a line sent to stdin
+//     > a line expected on stdout
+//
+// Run with `cargo test --test transcripts -- --bless` to regenerate the
+// transcripts from the current behavior instead of checking them. Any
+// other non-flag argument restricts the run to cases whose name contains
+// it.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+// lines of context to print around the first mismatch
+const CONTEXT_LINES: usize = 3;
+// lines of expected and actual output to print after the first mismatch
+const POST_ERROR_LINES: usize = 15;
+
+// Shared log of everything the REPL read and wrote, in order.
+#[derive(Default)]
+struct Log {
+    lines: Vec<String>,
+    partial: String,
+}
+
+impl Log {
+    fn finish(mut self) -> Vec<String> {
+        if !self.partial.is_empty() {
+            let rest = std::mem::take(&mut self.partial);
+            self.lines.push(format!("> {rest}"));
+        }
+        self.lines
+    }
+}
+
+// Feeds the REPL one line at a time, logging each line as it is consumed.
+struct Recorder {
+    log: Rc<RefCell<Log>>,
+    lines: Vec<String>,
+    next: usize,
+    offset: usize,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Recorder {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self.lines.get(self.next) {
+            Some(line) => Ok(&line.as_bytes()[self.offset..]),
+            None => Ok(&[]),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let Some(line) = self.lines.get(self.next) else {
+            return;
+        };
+        self.offset += amt;
+        if self.offset >= line.len() {
+            self.log.borrow_mut().lines.push(format!("< {line}"));
+            self.next += 1;
+            self.offset = 0;
+        }
+    }
+}
+
+// Collects the REPL's output into the shared log, one line at a time.
+struct Capture {
+    log: Rc<RefCell<Log>>,
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut log = self.log.borrow_mut();
+        log.partial.push_str(&String::from_utf8_lossy(buf));
+        while let Some(i) = log.partial.find('\n') {
+            let line: String = log.partial.drain(..=i).collect();
+            log.lines.push(format!("> {line}"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Run the REPL over the given input and return the transcript lines,
+// each still carrying its trailing newline.
+fn record(input: &str) -> Vec<String> {
+    let log = Rc::new(RefCell::new(Log::default()));
+    let lines = input.split_inclusive('\n').map(str::to_string).collect();
+    let reader = Recorder { log: log.clone(), lines, next: 0, offset: 0 };
+    let mut writer = Capture { log: log.clone() };
+    student::repl::run(reader, &mut writer).expect("REPL failed");
+    drop(writer);
+    match Rc::try_unwrap(log) {
+        Ok(log) => log.into_inner().finish(),
+        Err(_) => panic!("transcript log still shared"),
+    }
+}
+
+// Describe the first difference between two transcripts, or None if
+// they are identical.
+fn diff(expected: &[String], actual: &[String]) -> Option<String> {
+    let first = expected.iter().zip(actual).position(|(e, a)| e != a);
+    let first = match first {
+        Some(i) => i,
+        None if expected.len() == actual.len() => return None,
+        None => expected.len().min(actual.len()),
+    };
+
+    let mut report = String::new();
+    let start = first.saturating_sub(CONTEXT_LINES);
+    for line in &expected[start..first] {
+        report.push_str(&format!("  {line}"));
+    }
+    let end = |lines: &[String]| (first + POST_ERROR_LINES).min(lines.len());
+    report.push_str(&format!("mismatch at line {}:\n", first + 1));
+    for line in &expected[first..end(expected)] {
+        report.push_str(&format!("- {line}"));
+    }
+    if expected.len() <= first {
+        report.push_str("- <end of transcript>\n");
+    }
+    for line in &actual[first..end(actual)] {
+        report.push_str(&format!("+ {line}"));
+    }
+    if actual.len() <= first {
+        report.push_str("+ <end of output>\n");
+    }
+    Some(report)
+}
+
+fn cases(dir: &Path, filters: &[String]) -> Vec<PathBuf> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("cannot read inputs directory")
+        .map(|entry| entry.expect("cannot read directory entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "input"))
+        .filter(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy();
+            filters.is_empty() || filters.iter().any(|f| name.contains(f))
+        })
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+fn main() {
+    let mut bless = false;
+    let mut filters = Vec::new();
+    for arg in env::args().skip(1) {
+        if arg == "--bless" {
+            bless = true;
+        } else if !arg.starts_with('-') {
+            filters.push(arg);
+        }
+    }
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("inputs");
+    let cases = cases(&dir, &filters);
+    let mut failures = 0;
+
+    for input_path in &cases {
+        let name = input_path.file_stem().unwrap().to_string_lossy();
+        let transcript_path = input_path.with_extension("transcript");
+        let input = fs::read_to_string(input_path)
+            .unwrap_or_else(|e| panic!("cannot read {name}.input: {e}"));
+        let actual = record(&input);
+
+        if bless {
+            fs::write(&transcript_path, actual.concat()).unwrap_or_else(|e| {
+                panic!("cannot write {name}.transcript: {e}")
+            });
+            println!("blessed {name} ({} lines)", actual.len());
+            continue;
+        }
+
+        let expected: Vec<String> = match fs::read_to_string(&transcript_path) {
+            Ok(text) => {
+                text.split_inclusive('\n').map(str::to_string).collect()
+            }
+            Err(e) => {
+                println!("FAILED {name}: cannot read transcript: {e}");
+                failures += 1;
+                continue;
+            }
+        };
+        match diff(&expected, &actual) {
+            None => println!("ok     {name}"),
+            Some(report) => {
+                println!("FAILED {name}\n{report}");
+                failures += 1;
+            }
+        }
+    }
+
+    println!(
+        "\ntranscripts: {} passed, {failures} failed",
+        cases.len() - failures
+    );
+    io::stdout().flush().unwrap();
+    if failures > 0 {
+        process::exit(1);
+    }
+}